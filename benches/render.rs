@@ -0,0 +1,169 @@
+// Copyright 2022 Miguel Young de la Sota
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks of whole dumps of pseudorandom input, written to /dev/null.
+//!
+//! Set `OHX_BENCH_GIB` to also dump an input that many GiB long.
+
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use criterion::Throughput;
+
+const CASES: &[(&str, &[&str])] = &[
+  ("plain", &["--color", "never"]),
+  ("base16", &["--color", "truecolor"]),
+  ("base8", &["-b", "8", "--color", "truecolor"]),
+  ("base2", &["-b", "2", "--color", "truecolor"]),
+  ("formula", &["-x", "x3>>>1^", "--color", "truecolor"]),
+  (
+    "single-glyphs",
+    &["--color-single-glyphs", "--color", "truecolor"],
+  ),
+  ("jobs", &["-j", "4", "--color", "truecolor"]),
+];
+
+/// Formulas to compare ways of evaluating, each on lanes of one byte, where
+/// they'd get lookup tables, and three, where they'd get expression trees.
+const FORMULAS: &[(&str, &str)] = &[
+  ("ascii", "x ascii_class 50 *"),
+  ("arith", "x 3 >>> 1 ^ dup 0x55 & swap 2 * |"),
+];
+
+/// Writes `len` bytes of pseudorandom input to a temporary file.
+fn input(len: u64) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("0x-bench-{len}.bin"));
+  if fs::metadata(&path).is_ok_and(|m| m.len() == len) {
+    return path;
+  }
+
+  let mut out = BufWriter::new(File::create(&path).unwrap());
+  let mut x = 0x2545_u32;
+  for _ in 0..len {
+    x = x.wrapping_mul(1103515245).wrapping_add(12345);
+    out.write_all(&[(x >> 16) as u8]).unwrap();
+  }
+  out.flush().unwrap();
+  path
+}
+
+fn dump(args: &[&str], path: &Path) {
+  let status = Command::new(env!("CARGO_BIN_EXE_0x"))
+    .args(args)
+    .arg(path)
+    .stdout(Stdio::null())
+    .status()
+    .unwrap();
+  assert!(status.success());
+}
+
+/// Like `dump()`, but pipes the input in on stdin.
+fn dump_stdin(args: &[&str], path: &Path) {
+  let status = Command::new(env!("CARGO_BIN_EXE_0x"))
+    .args(args)
+    .stdin(File::open(path).unwrap())
+    .stdout(Stdio::null())
+    .status()
+    .unwrap();
+  assert!(status.success());
+}
+
+fn render(c: &mut Criterion) {
+  let len = 4 << 20;
+  let path = input(len);
+
+  let mut group = c.benchmark_group("render");
+  group.sample_size(10).throughput(Throughput::Bytes(len));
+  for &(name, args) in CASES {
+    group.bench_function(name, |b| b.iter(|| dump(args, &path)));
+  }
+  group.finish();
+}
+
+/// Compares lookup tables and expression trees against interpreting each
+/// formula's ops for every lane, as every byte used to be.
+fn calc(c: &mut Criterion) {
+  let len = 4 << 20;
+  let path = input(len);
+
+  let mut group = c.benchmark_group("calc");
+  group.sample_size(10).throughput(Throughput::Bytes(len));
+  for &(name, formula) in FORMULAS {
+    for lanes in ["1", "3"] {
+      for strategy in ["auto", "tree", "interpret"] {
+        // Without a table to build, auto and tree are the same.
+        if lanes == "3" && strategy == "tree" {
+          continue;
+        }
+        let args = [
+          "-b",
+          "8",
+          "--lanes",
+          lanes,
+          "-x",
+          formula,
+          "--calc-strategy",
+          strategy,
+          "--color",
+          "truecolor",
+        ];
+        let id = format!("{name}/lanes-{lanes}/{strategy}");
+        group.bench_function(id, |b| b.iter(|| dump(&args, &path)));
+      }
+    }
+  }
+  group.finish();
+}
+
+/// Dumps a file of several GiB, as set by `OHX_BENCH_GIB`. Each run takes
+/// minutes, so this is skipped unless asked for.
+fn large(c: &mut Criterion) {
+  let gib = std::env::var("OHX_BENCH_GIB").ok();
+  let Some(gib) = gib.and_then(|v| v.parse::<u64>().ok()) else {
+    return;
+  };
+  let len = gib << 30;
+  let path = input(len);
+
+  let mut group = c.benchmark_group("large");
+  group.sample_size(10).throughput(Throughput::Bytes(len));
+  group
+    .bench_function("plain", |b| b.iter(|| dump(&["--color", "never"], &path)));
+  group.bench_function("truecolor", |b| {
+    b.iter(|| dump(&["--color", "truecolor"], &path))
+  });
+  group.bench_function("one-thread", |b| {
+    b.iter(|| dump(&["-j", "1", "--color", "truecolor"], &path))
+  });
+  group.bench_function("interpret", |b| {
+    let args = ["--calc-strategy", "interpret", "--color", "truecolor"];
+    b.iter(|| dump(&args, &path))
+  });
+  group.bench_function("stdin", |b| {
+    b.iter(|| dump_stdin(&["--color", "truecolor"], &path))
+  });
+  group.finish();
+}
+
+criterion_group!(benches, render, calc, large);
+criterion_main!(benches);
@@ -0,0 +1,99 @@
+// Copyright 2022 Miguel Young de la Sota
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Annotation files, which mark parts of the input with a color and a note.
+//!
+//! Each line is a range of the input, in the same syntax as `--range`, then
+//! an optional color and an optional note:
+//!
+//! ```text
+//! # Lines starting with a hash are comments.
+//! 0x10..0x14   red      "this is the CRC"
+//! 0x20+4       #ffcc00  "this length is wrong"
+//! 0x40..0x48   "highlighted in the theme's highlight color"
+//! 0x50..0x60   skyblue
+//! ```
+
+use std::ops::Range;
+
+use palette::Srgb;
+
+use crate::input;
+
+/// A marked part of the input.
+#[derive(Clone, Debug)]
+pub struct Annotation {
+  pub range: Range<u64>,
+  /// The color to highlight the range with; if missing, the theme's
+  /// highlight color is used.
+  pub color: Option<Srgb<u8>>,
+  pub note: Option<String>,
+}
+
+/// The contents of an annotation file.
+#[derive(Clone, Debug, Default)]
+pub struct Annotations {
+  pub entries: Vec<Annotation>,
+}
+
+impl Annotations {
+  /// Parses an annotation file.
+  pub fn parse(src: &str) -> Result<Annotations, String> {
+    let mut entries = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let err = |e: String| format!("line {}: {e}", i + 1);
+      let (range, rest) =
+        line.split_once(char::is_whitespace).unwrap_or((line, ""));
+      let range = input::parse_range(range).map_err(err)?;
+
+      let rest = rest.trim();
+      let (color, note) = match rest.starts_with('"') {
+        true => (None, rest),
+        false => {
+          let (color, note) =
+            rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+          let color = Some(color)
+            .filter(|c| !c.is_empty())
+            .map(crate::parse_rgb)
+            .transpose()
+            .map_err(err)?;
+          (color, note.trim())
+        }
+      };
+      let note = note
+        .strip_prefix('"')
+        .and_then(|n| n.strip_suffix('"'))
+        .unwrap_or(note);
+
+      entries.push(Annotation {
+        range,
+        color,
+        note: Some(note).filter(|n| !n.is_empty()).map(String::from),
+      });
+    }
+    Ok(Annotations { entries })
+  }
+}
+
+impl argh::FromArgValue for Annotations {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    let src =
+      std::fs::read_to_string(value).map_err(|e| format!("{value}: {e}"))?;
+    Self::parse(&src).map_err(|e| format!("{value}: {e}"))
+  }
+}
@@ -13,168 +13,709 @@
 // limitations under the License.
 
 //! An extremely basic RPN calculator, for determining how to color-code bytes.
+//!
+//! The language is a tiny Forth: a formula is a sequence of numbers and
+//! operators, and new words can be defined with `: name ... ;`. Definitions
+//! are macros: they are expanded into the ops they contain when used.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 #[rustfmt::skip]
+#[derive(Copy, Clone)]
 pub enum Op {
   Add, Sub, Mul, Div, Rem,
   And, Or, Xor,
   Sll, Srl, Sra,
+  Eq, Ne, Lt, Gt, Le, Ge,
   Not, Neg,
 
-  X, Imm(u64),
+  Dup, Drop, Swap, Over, Rot,
+
+  X, Bits, Pos, Imm(u64),
 }
 
-#[derive(Default)]
+/// Words that are always available to formulas.
+///
+/// Classifiers return a small class index, which can be scaled up with `*` to
+/// spread it across the gradient.
+///
+/// Numbers are masked to the width of `x`, so the ranges in `ascii_class` and
+/// `utf8_class` only mean anything for inputs of at least 8 bits, and
+/// `high_nibble` for inputs of at least 4. With `--color-single-glyphs`, most
+/// bases give them narrower inputs than that.
+const PRELUDE: &str = r"
+  ( a lo hi -- a is in lo..=hi )
+  : between over - rot rot - swap <= ;
+
+  ( a -- 1 if a is zero )
+  : is_zero 0 = ;
+
+  ( a -- 1 if a's sign bit is set )
+  : sign bits 1 - >> ;
+
+  ( a -- the top four bits of a, which must be at least 4 bits wide )
+  : high_nibble bits 4 - >> ;
+
+  ( a -- 0 other, 1 upper, 2 lower, 3 digit, 4 punctuation; needs a byte )
+  : ascii_class
+    dup 65 90 between
+    over 97 122 between 2 * +
+    over 48 57 between 3 * +
+    swap
+    dup 33 47 between
+    over 58 64 between |
+    over 91 96 between |
+    swap 123 126 between |
+    4 * + ;
+
+  ( text attributes, for --style )
+  : bold 1 ;
+  : dim 2 ;
+  : underline 4 ;
+  : highlight 8 ;
+
+  ( a -- 0 ascii, 1 continuation, 2/3/4 leads of 2/3/4 byte sequences,
+    5 invalid; needs a byte )
+  : utf8_class
+    dup 127 >
+    over 191 > +
+    over 223 > +
+    over 239 > +
+    swap 247 > + ;
+";
+
+#[derive(Clone, Default)]
 pub struct Calc(Vec<Op>);
 
+/// The most lookup table entries [`Calc::compile()`] will build for a
+/// formula, across every position in a word, before it turns to expression
+/// trees instead.
+const MAX_TABLE_LEN: u64 = 1 << 16;
+
+/// How [`Calc::compile()`] should evaluate a formula.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Strategy {
+  /// Lookup tables for narrow inputs, and expression trees for wide ones or
+  /// when the tables would be too large.
+  #[default]
+  Auto,
+  /// Expression trees, even where a table would do, unless they'd be too
+  /// large.
+  Tree,
+  /// Interpret the formula's ops on every input.
+  Interpret,
+}
+
+impl argh::FromArgValue for Strategy {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    match value {
+      "auto" => Ok(Self::Auto),
+      "tree" => Ok(Self::Tree),
+      "interpret" => Ok(Self::Interpret),
+      _ => Err("expected `auto`, `tree`, or `interpret`".into()),
+    }
+  }
+}
+
 impl Calc {
-  pub fn execute(&self, x: u64, bits: u32, stack: &mut Vec<u64>) -> u64 {
-    stack.clear();
-    stack.push(x);
-    let mask = (1 << bits) - 1;
-    for op in &self.0 {
-      let val = match op {
-        Op::Add => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b.wrapping_add(a)
-        }
-        Op::Sub => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b.wrapping_sub(a)
+  /// Parses a formula, which may use any of the words in the prelude.
+  pub fn parse(src: &str) -> Result<Calc, String> {
+    let mut words = HashMap::new();
+    Parser::new(PRELUDE).parse(&mut words)?;
+    Parser::new(src).parse(&mut words).map(Calc)
+  }
+
+  /// Runs this formula on any kind of stack value, given ways to make leaves
+  /// (`X`, `Bits`, `Pos` and `Imm`) and apply operators.
+  ///
+  /// The stack behaves as if it had infinitely many zeros at the bottom.
+  fn run<T: Clone>(
+    &self,
+    stack: &mut Vec<T>,
+    mut leaf: impl FnMut(Op) -> T,
+    mut apply: impl FnMut(Op, T, T) -> T,
+  ) -> T {
+    let zero = leaf(Op::Imm(0));
+    let pop = |stack: &mut Vec<T>| stack.pop().unwrap_or_else(|| zero.clone());
+    for &op in &self.0 {
+      match op {
+        Op::X | Op::Bits | Op::Pos | Op::Imm(_) => stack.push(leaf(op)),
+        Op::Dup => {
+          let a = pop(stack);
+          stack.push(a.clone());
+          stack.push(a);
         }
-        Op::Mul => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b.wrapping_mul(a)
+        Op::Drop => {
+          pop(stack);
         }
-        Op::Div => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b.checked_div(a).unwrap_or(0xff)
+        Op::Swap => {
+          let a = pop(stack);
+          let b = pop(stack);
+          stack.push(a);
+          stack.push(b);
         }
-        Op::Rem => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b.checked_rem(a).unwrap_or(b)
+        Op::Over => {
+          let a = pop(stack);
+          let b = pop(stack);
+          stack.push(b.clone());
+          stack.push(a);
+          stack.push(b);
         }
-
-        Op::And => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b & a
+        Op::Rot => {
+          let c = pop(stack);
+          let b = pop(stack);
+          let a = pop(stack);
+          stack.push(b);
+          stack.push(c);
+          stack.push(a);
         }
-        Op::Or => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b | a
+        Op::Not | Op::Neg => {
+          let a = pop(stack);
+          let val = apply(op, zero.clone(), a);
+          stack.push(val);
         }
-        Op::Xor => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b ^ a
+        _ => {
+          let a = pop(stack);
+          let b = pop(stack);
+          let val = apply(op, b, a);
+          stack.push(val);
         }
+      }
+    }
+    pop(stack)
+  }
 
-        Op::Sll => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b.wrapping_shl(a as u32)
-        }
-        Op::Srl => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          b.wrapping_shr(a as u32)
-        }
-        Op::Sra => {
-          let a = stack.pop().unwrap_or(0);
-          let b = stack.pop().unwrap_or(0);
-          // Because where the sign bit is is dynamic, we need to shift in a
-          // bunch of sign bits first.
-          (b as i64)
-            .wrapping_shl(64 - bits)
-            .wrapping_shr(a as u32 + 64 - bits) as u64
-        }
+  /// Compiles this formula for inputs that are `bits` wide, in words of
+  /// `word_len` inputs.
+  ///
+  /// Narrow inputs are evaluated once for every possible value; wider ones are
+  /// turned into a tree of closures, with any constant subexpressions folded
+  /// away. Formulas that use `pos` are compiled separately for each position
+  /// in a word, so that within a position the result only depends on `x`.
+  /// Formulas whose trees would be larger than they are get interpreted.
+  pub fn compile(
+    &self,
+    bits: u32,
+    word_len: u32,
+    strategy: Strategy,
+  ) -> Compiled {
+    let positions = match self.0.iter().any(|op| matches!(op, Op::Pos)) {
+      true => word_len.max(1) as u64,
+      false => 1,
+    };
+    let table = strategy == Strategy::Auto
+      && bits <= MAX_TABLE_LEN.ilog2()
+      && positions << bits <= MAX_TABLE_LEN;
+    Compiled(
+      (0..positions)
+        .map(|pos| match strategy {
+          _ if table => self.table(bits, pos),
+          Strategy::Interpret => Kernel::Interpret(self.clone(), bits, pos),
+          _ => self.tree(bits, pos),
+        })
+        .collect(),
+    )
+  }
+
+  /// Evaluates this formula on every `bits`-wide input at `pos`.
+  fn table(&self, bits: u32, pos: u64) -> Kernel {
+    let mut stack = Vec::new();
+    let table = (0..1u64 << bits)
+      .map(|x| self.execute(x, bits, pos, &mut stack))
+      .collect();
+    Kernel::Table(table)
+  }
 
-        Op::Neg => {
-          let a = stack.pop().unwrap_or(0);
-          a.wrapping_neg()
+  /// Builds an expression tree for this formula at `pos`, or falls back to
+  /// interpreting it if the tree would have more nodes than it has ops.
+  fn tree(&self, bits: u32, pos: u64) -> Kernel {
+    // Run the formula symbolically: the stack holds expressions rather than
+    // values, along with how many nodes each would take as a tree.
+    // Subexpressions are shared, so copying them is cheap even when their
+    // trees would not be.
+    let leaf = |expr| (Rc::new(expr), 1usize);
+    let mut stack = vec![leaf(Expr::X)];
+    let (expr, len) = self.run(
+      &mut stack,
+      |op| {
+        leaf(match op {
+          Op::X => Expr::X,
+          Op::Bits => Expr::Const(bits as u64),
+          Op::Pos => Expr::Const(pos & mask(bits)),
+          Op::Imm(y) => Expr::Const(y & mask(bits)),
+          _ => unreachable!(),
+        })
+      },
+      |op, (b, b_len), (a, a_len)| {
+        let (expr, len) = match op {
+          Op::Not | Op::Neg => (Expr::Unary(op, a), a_len.saturating_add(1)),
+          _ => (
+            Expr::Binary(op, b, a),
+            b_len.saturating_add(a_len).saturating_add(1),
+          ),
+        };
+        match expr.fold(bits) {
+          Expr::Const(k) => leaf(Expr::Const(k)),
+          expr => (Rc::new(expr), len),
         }
-        Op::Not => {
-          let a = stack.pop().unwrap_or(0);
-          !a
+      },
+    );
+
+    match &*expr {
+      Expr::Const(k) => Kernel::Const(*k),
+      // A tree is only faster than interpreting when it's no bigger than the
+      // formula; `dup` and `over` copy whole subtrees, so each one can
+      // double its size.
+      _ if len > self.0.len() => Kernel::Interpret(self.clone(), bits, pos),
+      expr => Kernel::Tree(expr.to_closure(bits)),
+    }
+  }
+
+  /// Interprets this formula directly on `x` at position `pos` in its word,
+  /// using `stack` as scratch space.
+  pub fn execute(
+    &self,
+    x: u64,
+    bits: u32,
+    pos: u64,
+    stack: &mut Vec<u64>,
+  ) -> u64 {
+    stack.clear();
+    stack.push(x);
+    self.run(
+      stack,
+      |op| match op {
+        Op::X => x & mask(bits),
+        Op::Bits => bits as u64,
+        Op::Pos => pos & mask(bits),
+        Op::Imm(y) => y & mask(bits),
+        _ => unreachable!(),
+      },
+      |op, b, a| apply(op, b, a, bits),
+    )
+  }
+}
+
+/// Returns a mask of the low `bits` bits, for `bits` from 1 to 64.
+fn mask(bits: u32) -> u64 {
+  u64::MAX >> (64 - bits)
+}
+
+/// Evaluates a single operator on already-popped operands, masking the result
+/// to `bits` wide. Unary operators ignore `b`.
+fn apply(op: Op, b: u64, a: u64, bits: u32) -> u64 {
+  let mask = mask(bits);
+  let val = match op {
+    Op::Add => b.wrapping_add(a),
+    Op::Sub => b.wrapping_sub(a),
+    Op::Mul => b.wrapping_mul(a),
+    Op::Div => b.checked_div(a).unwrap_or(0xff),
+    Op::Rem => b.checked_rem(a).unwrap_or(b),
+
+    Op::And => b & a,
+    Op::Or => b | a,
+    Op::Xor => b ^ a,
+
+    Op::Sll => b.wrapping_shl(a as u32),
+    Op::Srl => b.wrapping_shr(a as u32),
+    Op::Sra => (b as i64)
+      .wrapping_shl(64 - bits)
+      .wrapping_shr(a as u32 + 64 - bits) as u64,
+
+    Op::Eq => (b == a) as u64,
+    Op::Ne => (b != a) as u64,
+    Op::Lt => (b < a) as u64,
+    Op::Gt => (b > a) as u64,
+    Op::Le => (b <= a) as u64,
+    Op::Ge => (b >= a) as u64,
+
+    Op::Neg => a.wrapping_neg(),
+    Op::Not => !a,
+
+    _ => unreachable!(),
+  };
+  val & mask
+}
+
+/// A formula after symbolic evaluation. Operands may be shared with other
+/// expressions.
+enum Expr {
+  X,
+  Const(u64),
+  Unary(Op, Rc<Expr>),
+  Binary(Op, Rc<Expr>, Rc<Expr>),
+}
+
+impl Expr {
+  /// Folds this expression into a constant, if all of its operands are.
+  fn fold(self, bits: u32) -> Expr {
+    match &self {
+      Expr::Unary(op, a) => match **a {
+        Expr::Const(a) => Expr::Const(apply(*op, 0, a, bits)),
+        _ => self,
+      },
+      Expr::Binary(op, b, a) => match (&**b, &**a) {
+        (&Expr::Const(b), &Expr::Const(a)) => {
+          Expr::Const(apply(*op, b, a, bits))
         }
+        _ => self,
+      },
+      _ => self,
+    }
+  }
 
-        Op::X => x,
-        Op::Imm(y) => *y as u64,
-      };
-      stack.push(val & mask);
+  /// Builds a closure that evaluates this expression. Shared operands are
+  /// built once for each place they're used.
+  fn to_closure(&self, bits: u32) -> Box<dyn Fn(u64) -> u64 + Send + Sync> {
+    let mask = mask(bits);
+    match *self {
+      Expr::X => Box::new(move |x| x & mask),
+      Expr::Const(k) => Box::new(move |_| k),
+      Expr::Unary(op, ref a) => {
+        let a = a.to_closure(bits);
+        Box::new(move |x| apply(op, 0, a(x), bits))
+      }
+      Expr::Binary(op, ref b, ref a) => {
+        let b = b.to_closure(bits);
+        let a = a.to_closure(bits);
+        Box::new(move |x| apply(op, b(x), a(x), bits))
+      }
+    }
+  }
+}
+
+/// A [`Calc`] that has been specialized for a particular input width.
+pub struct Compiled(Vec<Kernel>);
+
+impl Compiled {
+  /// Evaluates the formula on `x`, at position `pos` in its word.
+  pub fn eval(&self, x: u64, pos: u64) -> u64 {
+    let kernel = match &self.0[..] {
+      [kernel] => kernel,
+      kernels => &kernels[(pos % kernels.len() as u64) as usize],
+    };
+    match kernel {
+      Kernel::Const(k) => *k,
+      Kernel::Table(t) => t[x as usize],
+      Kernel::Tree(f) => f(x),
+      Kernel::Interpret(calc, bits, pos) => {
+        STACK.with_borrow_mut(|stack| calc.execute(x, *bits, *pos, stack))
+      }
     }
-    stack.pop().unwrap_or(0)
   }
 }
 
-impl argh::FromArgValue for Calc {
-  fn from_arg_value(mut value: &str) -> Result<Calc, String> {
+thread_local! {
+  /// Scratch space for [`Kernel::Interpret`].
+  static STACK: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A formula specialized for one position in a word.
+enum Kernel {
+  Const(u64),
+  Table(Vec<u64>),
+  Tree(Box<dyn Fn(u64) -> u64 + Send + Sync>),
+  /// The formula itself, with the width of its inputs and the position it's
+  /// at.
+  Interpret(Calc, u32, u64),
+}
+
+struct Parser<'src> {
+  src: &'src str,
+}
+
+impl<'src> Parser<'src> {
+  fn new(src: &'src str) -> Self {
+    Self { src }
+  }
+
+  /// Skips whitespace and comments. `( ... )` comments may span lines; `\`
+  /// comments run to the end of the line.
+  fn skip_space(&mut self) -> Result<(), String> {
+    loop {
+      self.src = self.src.trim_start();
+      if let Some(rest) = self.src.strip_prefix('(') {
+        let end = rest.find(')').ok_or("unterminated comment")?;
+        self.src = &rest[end + 1..];
+      } else if let Some(rest) = self.src.strip_prefix('\\') {
+        self.src = rest.find('\n').map(|i| &rest[i..]).unwrap_or("");
+      } else {
+        return Ok(());
+      }
+    }
+  }
+
+  /// Takes the longest identifier-like prefix of the input.
+  fn word(&mut self) -> &'src str {
+    let end = self
+      .src
+      .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+      .unwrap_or(self.src.len());
+    let (word, rest) = self.src.split_at(end);
+    self.src = rest;
+    word
+  }
+
+  /// Parses ops until the end of input, recording new definitions in
+  /// `words`.
+  fn parse(
+    &mut self,
+    words: &mut HashMap<String, Vec<Op>>,
+  ) -> Result<Vec<Op>, String> {
     let mut ops = Vec::new();
-    while let Some(first) = value.chars().next() {
-      let op = match first {
-        ' ' | '\t' | '\r' | '\n' => continue,
+    // The definition currently being parsed, if any.
+    let mut defining: Option<(&str, Vec<Op>)> = None;
+    loop {
+      self.skip_space()?;
+      let Some(first) = self.src.chars().next() else {
+        break;
+      };
+
+      let ops = match &mut defining {
+        Some((_, body)) => body,
+        None => &mut ops,
+      };
+
+      match first {
         '0'..='9' => {
           let mut base = 10;
-          if let Some(trimmed) = value.strip_prefix("0x") {
-            value = trimmed;
+          if let Some(trimmed) = self.src.strip_prefix("0x") {
+            self.src = trimmed;
             base = 16;
           }
 
-          let digits_end =
-            match value.find(|c: char| !c.is_ascii_alphanumeric()) {
-              Some(idx) => idx,
-              None => value.len(),
-            };
-          let digits = &value[..digits_end];
-          value = &value[digits_end..];
-
+          let digits = self.word();
           let value =
             u64::from_str_radix(digits, base).map_err(|e| e.to_string())?;
           ops.push(Op::Imm(value));
           continue;
         }
 
-        '>' | '<' => {
-          let op = if let Some(rest) = value.strip_prefix("<<") {
-            value = rest;
-            Op::Sll
-          } else if let Some(rest) = value.strip_prefix(">>>") {
-            value = rest;
-            Op::Sra
-          } else if let Some(rest) = value.strip_prefix(">>") {
-            value = rest;
-            Op::Srl
-          } else {
-            return Err("unrecognized shift operator".into());
+        'a'..='z' | 'A'..='Z' | '_' => {
+          let start = self.src;
+          let word = self.word();
+          if let Some(body) = words.get(word) {
+            ops.extend_from_slice(body);
+            continue;
+          }
+
+          let op = match word {
+            "dup" => Op::Dup,
+            "drop" => Op::Drop,
+            "swap" => Op::Swap,
+            "over" => Op::Over,
+            "rot" => Op::Rot,
+            "bits" => Op::Bits,
+            "pos" => Op::Pos,
+            // `x` may be immediately followed by more ops, such as in `x0x10*`.
+            _ if word.starts_with('x') => {
+              self.src = &start[1..];
+              Op::X
+            }
+            _ => return Err(format!("unknown word: {word}")),
           };
           ops.push(op);
           continue;
         }
 
-        '+' => Op::Add,
-        '-' => Op::Sub,
-        '*' => Op::Mul,
-        '/' => Op::Div,
-        '%' => Op::Rem,
-        '~' => Op::Neg,
-        '&' => Op::And,
-        '|' => Op::Or,
-        '^' => Op::Xor,
-        '!' => Op::Not,
-        'x' => Op::X,
-        _ => return Err(format!("unrecognized character: {first}")),
-      };
+        ':' => {
+          if let Some((name, _)) = defining {
+            return Err(format!("nested definition inside of {name}"));
+          }
+          self.src = &self.src[1..];
+          self.skip_space()?;
+          let name = self.word();
+          if name.is_empty() {
+            return Err("expected a name after `:`".into());
+          }
+          defining = Some((name, Vec::new()));
+          continue;
+        }
+        ';' => {
+          let (name, body) = defining.take().ok_or("`;` outside definition")?;
+          words.insert(name.to_string(), body);
+          self.src = &self.src[1..];
+          continue;
+        }
+
+        _ => {}
+      }
 
+      let symbols = [
+        (">>>", Op::Sra),
+        (">>", Op::Srl),
+        ("<<", Op::Sll),
+        ("<=", Op::Le),
+        (">=", Op::Ge),
+        ("!=", Op::Ne),
+        ("<", Op::Lt),
+        (">", Op::Gt),
+        ("=", Op::Eq),
+        ("+", Op::Add),
+        ("-", Op::Sub),
+        ("*", Op::Mul),
+        ("/", Op::Div),
+        ("%", Op::Rem),
+        ("~", Op::Neg),
+        ("&", Op::And),
+        ("|", Op::Or),
+        ("^", Op::Xor),
+        ("!", Op::Not),
+      ];
+      let Some((rest, op)) = symbols
+        .iter()
+        .find_map(|&(sym, op)| Some((self.src.strip_prefix(sym)?, op)))
+      else {
+        return Err(format!("unrecognized character: {first}"));
+      };
+      self.src = rest;
       ops.push(op);
-      value = &value[1..];
     }
-    Ok(Calc(ops))
+
+    if let Some((name, _)) = defining {
+      return Err(format!("unterminated definition of {name}"));
+    }
+    Ok(ops)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Runs `src` on an `x` that is `bits` wide, at the start of a word.
+  fn eval(src: &str, bits: u32, x: u64) -> u64 {
+    Calc::parse(src)
+      .unwrap()
+      .compile(bits, 1, Strategy::Auto)
+      .eval(x, 0)
+  }
+
+  #[test]
+  fn arithmetic() {
+    assert_eq!(eval("x 3 +", 8, 4), 7);
+    assert_eq!(eval("x3+", 8, 4), 7);
+    assert_eq!(eval("x0x10*", 8, 3), 0x30);
+    assert_eq!(eval("x 1 -", 8, 0), 0xff);
+    assert_eq!(eval("x 4 >>", 8, 0xab), 0xa);
+    assert_eq!(eval("x 1 >>>", 8, 0x80), 0xc0);
+    // Division by zero gives all ones, and remainder by zero its dividend.
+    assert_eq!(eval("x 0 /", 8, 5), 0xff);
+    assert_eq!(eval("x 0 %", 8, 5), 5);
+    assert_eq!(eval("x 2 swap -", 8, 1), 1);
+    assert_eq!(eval("1 2 3 rot", 8, 0), 1);
+    assert_eq!(eval("+", 8, 9), 9);
+  }
+
+  #[test]
+  fn comments() {
+    assert_eq!(eval("x ( one ) 1 +", 8, 1), 2);
+    assert_eq!(eval("x ( spans\nlines ) 2 *", 8, 3), 6);
+    assert_eq!(eval("x 1 + \\ to the end\n 2 *", 8, 1), 4);
+    assert_eq!(eval("x \\ trailing", 8, 7), 7);
+  }
+
+  #[test]
+  fn definitions() {
+    assert_eq!(eval(": double 2 * ; x double double", 8, 3), 12);
+    assert_eq!(eval(": a 1 + ; : b a a ; x b", 8, 1), 3);
+    // Words can be redefined, including ones from the prelude.
+    assert_eq!(eval(": sign 7 ; x sign", 8, 0), 7);
+    // Definitions are expanded where they're used, so later ones don't
+    // change earlier uses.
+    assert_eq!(eval(": f 1 ; : g f ; : f 2 ; g f +", 8, 0), 3);
+  }
+
+  #[test]
+  fn errors() {
+    let err = |src| Calc::parse(src).err().unwrap();
+    assert_eq!(err("x ( open"), "unterminated comment");
+    assert_eq!(err("x frob"), "unknown word: frob");
+    assert_eq!(err("x $"), "unrecognized character: $");
+    assert_eq!(err(": f : g ; ;"), "nested definition inside of f");
+    assert_eq!(err(": ;"), "expected a name after `:`");
+    assert_eq!(err("x ;"), "`;` outside definition");
+    assert_eq!(err(": f 1"), "unterminated definition of f");
+    assert!(Calc::parse("0xzz").is_err());
+  }
+
+  #[test]
+  fn prelude() {
+    let class = |b: u8| eval("x ascii_class", 8, b as u64);
+    assert_eq!(class(b'\0'), 0);
+    assert_eq!(class(b'Q'), 1);
+    assert_eq!(class(b'q'), 2);
+    assert_eq!(class(b'7'), 3);
+    assert_eq!(class(b'~'), 4);
+    assert_eq!(class(b' '), 0);
+
+    let utf8 = |b: u8| eval("x utf8_class", 8, b as u64);
+    assert_eq!(utf8(b'a'), 0);
+    assert_eq!(utf8(0x80), 1);
+    assert_eq!(utf8(0xc3), 2);
+    assert_eq!(utf8(0xe2), 3);
+    assert_eq!(utf8(0xf0), 4);
+    assert_eq!(utf8(0xff), 5);
+
+    assert_eq!(eval("x is_zero", 8, 0), 1);
+    assert_eq!(eval("x is_zero", 8, 1), 0);
+    assert_eq!(eval("x sign", 8, 0x80), 1);
+    assert_eq!(eval("x sign", 16, 0x80), 0);
+    assert_eq!(eval("x high_nibble", 8, 0xab), 0xa);
+    assert_eq!(eval("x high_nibble", 4, 0xb), 0xb);
+    assert_eq!(eval("x 3 5 between", 8, 5), 1);
+    assert_eq!(eval("x 3 5 between", 8, 6), 0);
+    assert_eq!(eval("bold underline |", 8, 0), 5);
+  }
+
+  /// Wide inputs are compiled to a tree rather than a table, and should
+  /// agree with it.
+  #[test]
+  fn tree() {
+    let src = "x 3 >>> 1 ^ dup 0x55 & swap 2 * |";
+    let calc = Calc::parse(src).unwrap();
+    let wide = calc.compile(24, 1, Strategy::Auto);
+    let mut stack = Vec::new();
+    for x in [0, 1, 0x7fffff, 0x800000, 0xabcdef, 0xffffff] {
+      assert_eq!(wide.eval(x, 0), calc.execute(x, 24, 0, &mut stack));
+    }
+  }
+
+  /// Every strategy should agree with interpreting the formula, at every
+  /// position.
+  #[test]
+  fn strategies() {
+    let calc = Calc::parse("x ascii_class pos 3 * + x sign |").unwrap();
+    let mut stack = Vec::new();
+    for bits in [4, 8, 16, 24] {
+      for strategy in [Strategy::Auto, Strategy::Tree, Strategy::Interpret] {
+        let compiled = calc.compile(bits, 3, strategy);
+        for pos in 0..3 {
+          for x in [0, 0x41, 0x7f, 0x80, 0xff, 0xabcd, 0xffffff] {
+            let x = x & mask(bits);
+            assert_eq!(
+              compiled.eval(x, pos),
+              calc.execute(x, bits, pos, &mut stack),
+              "{strategy:?} at {bits} bits, pos {pos}, x {x:#x}",
+            );
+          }
+        }
+      }
+    }
+  }
+
+  /// Each `dup` doubles the size of the formula's tree, which shouldn't make
+  /// compiling or running it any slower than interpreting it.
+  #[test]
+  fn long_dup_chain() {
+    let src = format!("x{}", " dup *".repeat(64));
+    let calc = Calc::parse(&src).unwrap();
+    let compiled = calc.compile(24, 1, Strategy::Auto);
+    let mut stack = Vec::new();
+    for x in [0, 1, 3, 0xabcdef] {
+      assert_eq!(compiled.eval(x, 0), calc.execute(x, 24, 0, &mut stack));
+    }
   }
 }
@@ -0,0 +1,291 @@
+// Copyright 2022 Miguel Young de la Sota
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checksums and hashes of the dumped bytes.
+
+use crc::Crc;
+use sha2::Digest as _;
+
+const CRC8: Crc<u8> = Crc::<u8>::new(&crc::CRC_8_SMBUS);
+const CRC8_MAXIM: Crc<u8> = Crc::<u8>::new(&crc::CRC_8_MAXIM_DOW);
+const CRC16_ARC: Crc<u16> = Crc::<u16>::new(&crc::CRC_16_ARC);
+const CRC16_CCITT: Crc<u16> = Crc::<u16>::new(&crc::CRC_16_IBM_3740);
+const CRC16_XMODEM: Crc<u16> = Crc::<u16>::new(&crc::CRC_16_XMODEM);
+const CRC16_MODBUS: Crc<u16> = Crc::<u16>::new(&crc::CRC_16_MODBUS);
+const CRC32: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+const CRC32C: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISCSI);
+const CRC32_MPEG2: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_MPEG_2);
+const CRC32_BZIP2: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_BZIP2);
+
+/// A checksum or hash function.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Algorithm {
+  /// CRC-8/SMBUS, the plain CRC-8.
+  Crc8,
+  /// CRC-8/MAXIM-DOW, used by 1-Wire devices.
+  Crc8Maxim,
+  /// CRC-16/ARC, the plain CRC-16.
+  Crc16,
+  /// CRC-16/IBM-3740, often called CRC-16/CCITT-FALSE.
+  Crc16Ccitt,
+  Crc16Xmodem,
+  Crc16Modbus,
+  /// CRC-32/ISO-HDLC, as used by zlib, PNG, and Ethernet.
+  Crc32,
+  /// CRC-32/ISCSI, or Castagnoli.
+  Crc32c,
+  Crc32Mpeg2,
+  Crc32Bzip2,
+  Adler32,
+  /// 32-bit FNV-1a.
+  Fnv32,
+  /// 64-bit FNV-1a.
+  Fnv64,
+  Sha256,
+  Sha1,
+  Md5,
+}
+
+impl Algorithm {
+  /// Every algorithm, with the name it's given on the command line.
+  const ALL: &[(&str, Algorithm)] = &[
+    ("crc8", Self::Crc8),
+    ("crc8-maxim", Self::Crc8Maxim),
+    ("crc16", Self::Crc16),
+    ("crc16-ccitt", Self::Crc16Ccitt),
+    ("crc16-xmodem", Self::Crc16Xmodem),
+    ("crc16-modbus", Self::Crc16Modbus),
+    ("crc32", Self::Crc32),
+    ("crc32c", Self::Crc32c),
+    ("crc32-mpeg2", Self::Crc32Mpeg2),
+    ("crc32-bzip2", Self::Crc32Bzip2),
+    ("adler32", Self::Adler32),
+    ("fnv32", Self::Fnv32),
+    ("fnv64", Self::Fnv64),
+    ("sha256", Self::Sha256),
+    ("sha1", Self::Sha1),
+    ("md5", Self::Md5),
+  ];
+
+  pub fn name(self) -> &'static str {
+    Self::ALL
+      .iter()
+      .find(|(_, a)| *a == self)
+      .map_or("", |(name, _)| name)
+  }
+
+  /// Starts computing this algorithm.
+  pub fn start(self) -> State {
+    match self {
+      Self::Crc8 => State::Crc8(CRC8.digest()),
+      Self::Crc8Maxim => State::Crc8(CRC8_MAXIM.digest()),
+      Self::Crc16 => State::Crc16(CRC16_ARC.digest()),
+      Self::Crc16Ccitt => State::Crc16(CRC16_CCITT.digest()),
+      Self::Crc16Xmodem => State::Crc16(CRC16_XMODEM.digest()),
+      Self::Crc16Modbus => State::Crc16(CRC16_MODBUS.digest()),
+      Self::Crc32 => State::Crc32(CRC32.digest()),
+      Self::Crc32c => State::Crc32(CRC32C.digest()),
+      Self::Crc32Mpeg2 => State::Crc32(CRC32_MPEG2.digest()),
+      Self::Crc32Bzip2 => State::Crc32(CRC32_BZIP2.digest()),
+      Self::Adler32 => State::Adler32(1, 0),
+      Self::Fnv32 => State::Fnv32(0x811c9dc5),
+      Self::Fnv64 => State::Fnv64(0xcbf29ce484222325),
+      Self::Sha256 => State::Sha256(sha2::Sha256::new()),
+      Self::Sha1 => State::Sha1(sha1::Sha1::new()),
+      Self::Md5 => State::Md5(md5::Md5::new()),
+    }
+  }
+
+  /// Computes this algorithm over `data`, written out in hex.
+  pub fn sum(self, data: &[u8]) -> String {
+    let mut state = self.start();
+    state.update(data);
+    state.finish()
+  }
+
+  /// How many hex digits this algorithm's result is written in.
+  pub fn hex_len(self) -> usize {
+    self.sum(&[]).len()
+  }
+}
+
+impl argh::FromArgValue for Algorithm {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    let value = value.to_lowercase().replace('_', "-");
+    let alias = match value.as_str() {
+      "crc-8" => "crc8",
+      "crc-16" | "crc16-arc" => "crc16",
+      "crc16-ccitt-false" | "crc16-ibm-3740" => "crc16-ccitt",
+      "crc-32" => "crc32",
+      "fnv" | "fnv1a" | "fnv1a-32" => "fnv32",
+      "fnv1a-64" => "fnv64",
+      "sha-256" => "sha256",
+      "sha-1" => "sha1",
+      "adler" | "adler-32" => "adler32",
+      value => value,
+    };
+    Self::ALL
+      .iter()
+      .find(|(name, _)| *name == alias)
+      .map(|&(_, a)| a)
+      .ok_or_else(|| {
+        let names = Self::ALL.iter().map(|(name, _)| *name);
+        format!("expected one of {}", names.collect::<Vec<_>>().join(", "))
+      })
+  }
+}
+
+/// A checksum or hash partway through being computed.
+pub enum State {
+  Crc8(crc::Digest<'static, u8>),
+  Crc16(crc::Digest<'static, u16>),
+  Crc32(crc::Digest<'static, u32>),
+  /// The running sums `a` and `b`.
+  Adler32(u32, u32),
+  Fnv32(u32),
+  Fnv64(u64),
+  Sha256(sha2::Sha256),
+  Sha1(sha1::Sha1),
+  Md5(md5::Md5),
+}
+
+/// The modulus of Adler-32's sums.
+const ADLER_MOD: u32 = 65521;
+
+impl State {
+  pub fn update(&mut self, data: &[u8]) {
+    match self {
+      Self::Crc8(d) => d.update(data),
+      Self::Crc16(d) => d.update(data),
+      Self::Crc32(d) => d.update(data),
+      Self::Adler32(a, b) => {
+        // Sums are only reduced every so often, as late as they can be
+        // without overflowing.
+        for chunk in data.chunks(5552) {
+          for &byte in chunk {
+            *a += byte as u32;
+            *b += *a;
+          }
+          *a %= ADLER_MOD;
+          *b %= ADLER_MOD;
+        }
+      }
+      Self::Fnv32(h) => {
+        for &byte in data {
+          *h = (*h ^ byte as u32).wrapping_mul(0x01000193);
+        }
+      }
+      Self::Fnv64(h) => {
+        for &byte in data {
+          *h = (*h ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+      }
+      Self::Sha256(d) => d.update(data),
+      Self::Sha1(d) => d.update(data),
+      Self::Md5(d) => d.update(data),
+    }
+  }
+
+  /// Finishes the computation, returning the result in hex. Checksums are
+  /// written as numbers, and hashes as bytes.
+  pub fn finish(self) -> String {
+    let bytes = |b: &[u8]| b.iter().map(|b| format!("{b:02x}")).collect();
+    match self {
+      Self::Crc8(d) => format!("{:02x}", d.finalize()),
+      Self::Crc16(d) => format!("{:04x}", d.finalize()),
+      Self::Crc32(d) => format!("{:08x}", d.finalize()),
+      Self::Adler32(a, b) => format!("{:08x}", b << 16 | a),
+      Self::Fnv32(h) => format!("{h:08x}"),
+      Self::Fnv64(h) => format!("{h:016x}"),
+      Self::Sha256(d) => bytes(&d.finalize()),
+      Self::Sha1(d) => bytes(&d.finalize()),
+      Self::Md5(d) => bytes(&d.finalize()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The check values from the CRC catalogue, and known answers for
+  /// everything else.
+  #[test]
+  fn known_answers() {
+    let check = b"123456789";
+    let cases: &[(Algorithm, &[u8], &str)] = &[
+      (Algorithm::Crc8, check, "f4"),
+      (Algorithm::Crc8Maxim, check, "a1"),
+      (Algorithm::Crc16, check, "bb3d"),
+      (Algorithm::Crc16Ccitt, check, "29b1"),
+      (Algorithm::Crc16Xmodem, check, "31c3"),
+      (Algorithm::Crc16Modbus, check, "4b37"),
+      (Algorithm::Crc32, check, "cbf43926"),
+      (Algorithm::Crc32c, check, "e3069283"),
+      (Algorithm::Crc32Mpeg2, check, "0376e6e7"),
+      (Algorithm::Crc32Bzip2, check, "fc891918"),
+      (Algorithm::Adler32, b"", "00000001"),
+      (Algorithm::Adler32, b"Wikipedia", "11e60398"),
+      (Algorithm::Fnv32, b"", "811c9dc5"),
+      (Algorithm::Fnv32, b"a", "e40c292c"),
+      (Algorithm::Fnv32, b"foobar", "bf9cf968"),
+      (Algorithm::Fnv64, b"", "cbf29ce484222325"),
+      (Algorithm::Fnv64, b"a", "af63dc4c8601ec8c"),
+      (Algorithm::Fnv64, b"foobar", "85944171f73967e8"),
+      (
+        Algorithm::Sha256,
+        b"abc",
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+      ),
+      (
+        Algorithm::Sha1,
+        b"abc",
+        "a9993e364706816aba3e25717850c26c9cd0d89d",
+      ),
+      (Algorithm::Md5, b"abc", "900150983cd24fb0d6963f7d28e17f72"),
+    ];
+    for &(algorithm, data, sum) in cases {
+      assert_eq!(algorithm.sum(data), sum, "{algorithm:?} of {data:?}");
+      assert_eq!(algorithm.hex_len(), sum.len(), "{algorithm:?}");
+    }
+  }
+
+  /// Adler-32 only reduces its sums every so often, which shouldn't change
+  /// the result however the input is split up.
+  #[test]
+  fn adler32_reduction() {
+    let data = (0..100000).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>();
+    assert_eq!(Algorithm::Adler32.sum(&data), "437bc42e");
+    // The largest sums come from the largest bytes.
+    assert_eq!(Algorithm::Adler32.sum(&[0xff; 100000]), "149a302c");
+
+    let mut state = Algorithm::Adler32.start();
+    for chunk in data.chunks(1000) {
+      state.update(chunk);
+    }
+    assert_eq!(state.finish(), "437bc42e");
+  }
+
+  #[test]
+  fn names() {
+    let parse = <Algorithm as argh::FromArgValue>::from_arg_value;
+    for &(name, algorithm) in Algorithm::ALL {
+      assert_eq!(algorithm.name(), name);
+      assert_eq!(parse(name), Ok(algorithm));
+    }
+    assert_eq!(parse("CRC_32"), Ok(Algorithm::Crc32));
+    assert_eq!(parse("sha-256"), Ok(Algorithm::Sha256));
+    assert!(parse("crc64").is_err());
+  }
+}
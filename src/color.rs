@@ -16,48 +16,115 @@
 
 #![allow(unused)]
 
+use std::env;
+use std::fs;
 use std::io;
+use std::path::Path;
+use std::path::PathBuf;
 
 use palette::gradient::Gradient;
 use palette::ColorDifference;
 use palette::Hsv;
 use palette::IntoColor;
 use palette::Lab;
+use palette::Lch;
+use palette::LinSrgb;
+use palette::Mix;
+use palette::Oklab;
 use palette::Srgb;
 
-pub fn make_gradient(colors: &[Srgb<u8>], len: usize) -> Vec<Srgb<u8>> {
-  let domain = colors
-    .iter()
-    .enumerate()
-    .map(|(i, c)| {
-      let color: Hsv = c.into_format::<f32>().into_color();
-      (i as f32 / (len as f32 - 1.0), color)
-    })
-    .collect::<Vec<_>>();
-  Gradient::with_domain(domain)
-    .take(len)
-    .map(|color| {
-      let rgb: Srgb = color.into_color();
-      rgb.into_format::<u8>()
-    })
+/// A color space that gradients can be interpolated in.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum Space {
+  #[default]
+  Hsv,
+  LinearRgb,
+  Lab,
+  Lch,
+  Oklab,
+}
+
+impl argh::FromArgValue for Space {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    match value.to_lowercase().as_str() {
+      "hsv" => Ok(Self::Hsv),
+      "rgb" | "linear" | "linear-rgb" => Ok(Self::LinearRgb),
+      "lab" => Ok(Self::Lab),
+      "lch" => Ok(Self::Lch),
+      "oklab" => Ok(Self::Oklab),
+      _ => Err("expected `hsv`, `rgb`, `lab`, `lch`, or `oklab`".into()),
+    }
+  }
+}
+
+/// A color on a gradient, positioned between 0 and 1.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Stop {
+  pub pos: f32,
+  pub color: Srgb<u8>,
+}
+
+impl Stop {
+  /// Spreads `colors` evenly from 0 to 1.
+  pub fn spread(colors: impl IntoIterator<Item = Srgb<u8>>) -> Vec<Stop> {
+    let colors = colors.into_iter().collect::<Vec<_>>();
+    let last = (colors.len() as f32 - 1.0).max(1.0);
+    colors
+      .into_iter()
+      .enumerate()
+      .map(|(i, color)| Stop {
+        pos: i as f32 / last,
+        color,
+      })
+      .collect()
+  }
+}
+
+/// Samples `len` evenly-spaced colors along the gradient through `stops`,
+/// interpolating in `space`.
+fn sample(stops: &[Stop], len: usize, space: Space) -> Vec<Srgb> {
+  fn sample_in<C>(stops: &[Stop], len: usize) -> Vec<Srgb>
+  where
+    C: Mix<Scalar = f32> + Clone + IntoColor<Srgb>,
+    Srgb: IntoColor<C>,
+  {
+    let domain = stops
+      .iter()
+      .map(|s| (s.pos, s.color.into_format::<f32>().into_color()))
+      .collect::<Vec<(f32, C)>>();
+    let gradient = Gradient::with_domain(domain);
+    (0..len)
+      .map(|i| gradient.get(i as f32 / (len as f32 - 1.0)).into_color())
+      .collect()
+  }
+
+  match space {
+    Space::Hsv => sample_in::<Hsv>(stops, len),
+    Space::LinearRgb => sample_in::<LinSrgb>(stops, len),
+    Space::Lab => sample_in::<Lab>(stops, len),
+    Space::Lch => sample_in::<Lch>(stops, len),
+    Space::Oklab => sample_in::<Oklab>(stops, len),
+  }
+}
+
+pub fn make_gradient(
+  stops: &[Stop],
+  len: usize,
+  space: Space,
+) -> Vec<Srgb<u8>> {
+  sample(stops, len, space)
+    .into_iter()
+    .map(|rgb| rgb.into_format::<u8>())
     .collect()
 }
 
 pub fn make_quantized_gradient(
-  colors: &[Srgb<u8>],
+  stops: &[Stop],
   len: usize,
+  space: Space,
   palette: &[Lab],
 ) -> Vec<usize> {
-  let domain = colors
-    .iter()
-    .enumerate()
-    .map(|(i, c)| {
-      let color: Hsv = c.into_format::<f32>().into_color();
-      (i as f32 / (len as f32 - 1.0), color)
-    })
-    .collect::<Vec<_>>();
-
-  quantize(Gradient::with_domain(domain).take(len), &palette)
+  quantize(sample(stops, len, space), palette)
 }
 
 pub fn quantize_rgb<I>(iter: I, palette: &[Lab]) -> Vec<usize>
@@ -93,6 +160,391 @@ where
   quanta
 }
 
+/// A color vision deficiency that can be simulated, so that colors can be
+/// checked by people who don't have it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Deficiency {
+  Protanopia,
+  Deuteranopia,
+  Tritanopia,
+}
+
+impl argh::FromArgValue for Deficiency {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    match value.to_lowercase().as_str() {
+      "protanopia" | "protan" => Ok(Self::Protanopia),
+      "deuteranopia" | "deutan" => Ok(Self::Deuteranopia),
+      "tritanopia" | "tritan" => Ok(Self::Tritanopia),
+      _ => Err("expected `protanopia`, `deuteranopia`, or `tritanopia`".into()),
+    }
+  }
+}
+
+impl Deficiency {
+  /// Returns roughly how `color` looks to someone with this deficiency.
+  ///
+  /// This uses the full-severity matrices from Machado, Oliveira, and
+  /// Fernandes (2009), which operate on linear RGB.
+  pub fn simulate(self, color: Srgb<u8>) -> Srgb<u8> {
+    let m = match self {
+      Self::Protanopia => [
+        [0.152286, 1.052583, -0.204868],
+        [0.114503, 0.786281, 0.099216],
+        [-0.003882, -0.048116, 1.051998],
+      ],
+      Self::Deuteranopia => [
+        [0.367322, 0.860646, -0.227968],
+        [0.280085, 0.672501, 0.047413],
+        [-0.011820, 0.042940, 0.968881],
+      ],
+      Self::Tritanopia => [
+        [1.255528, -0.076749, -0.178779],
+        [-0.078411, 0.930809, 0.147602],
+        [0.004733, 0.691367, 0.303900],
+      ],
+    };
+    let lin: LinSrgb = color.into_format::<f32>().into_linear();
+    let rgb = [lin.red, lin.green, lin.blue];
+    let [r, g, b] = m.map(|row| {
+      let c = row.iter().zip(rgb).map(|(m, c)| m * c).sum::<f32>();
+      c.clamp(0.0, 1.0)
+    });
+    Srgb::from_linear(LinSrgb::new(r, g, b)).into_format()
+  }
+}
+
+/// Wong's adaptation of the Okabe-Ito palette, which stays distinct under all
+/// common color vision deficiencies. Black is left out, since it would vanish
+/// on most terminals.
+pub const OKABE_ITO: [colorous::Color; 7] = [
+  colorous::Color {
+    r: 0xe6,
+    g: 0x9f,
+    b: 0x00,
+  },
+  colorous::Color {
+    r: 0x56,
+    g: 0xb4,
+    b: 0xe9,
+  },
+  colorous::Color {
+    r: 0x00,
+    g: 0x9e,
+    b: 0x73,
+  },
+  colorous::Color {
+    r: 0xf0,
+    g: 0xe4,
+    b: 0x42,
+  },
+  colorous::Color {
+    r: 0x00,
+    g: 0x72,
+    b: 0xb2,
+  },
+  colorous::Color {
+    r: 0xd5,
+    g: 0x5e,
+    b: 0x00,
+  },
+  colorous::Color {
+    r: 0xcc,
+    g: 0x79,
+    b: 0xa7,
+  },
+];
+
+/// Paul Tol's "bright" qualitative scheme, which is also safe for color vision
+/// deficiencies.
+pub const TOL_BRIGHT: [colorous::Color; 7] = [
+  colorous::Color {
+    r: 0x44,
+    g: 0x77,
+    b: 0xaa,
+  },
+  colorous::Color {
+    r: 0x66,
+    g: 0xcc,
+    b: 0xee,
+  },
+  colorous::Color {
+    r: 0x22,
+    g: 0x88,
+    b: 0x33,
+  },
+  colorous::Color {
+    r: 0xcc,
+    g: 0xbb,
+    b: 0x44,
+  },
+  colorous::Color {
+    r: 0xee,
+    g: 0x66,
+    b: 0x77,
+  },
+  colorous::Color {
+    r: 0xaa,
+    g: 0x33,
+    b: 0x77,
+  },
+  colorous::Color {
+    r: 0xbb,
+    g: 0xbb,
+    b: 0xbb,
+  },
+];
+
+/// Paul Tol's "vibrant" qualitative scheme, a higher-contrast alternative to
+/// [`TOL_BRIGHT`].
+pub const TOL_VIBRANT: [colorous::Color; 7] = [
+  colorous::Color {
+    r: 0x00,
+    g: 0x77,
+    b: 0xbb,
+  },
+  colorous::Color {
+    r: 0x33,
+    g: 0xbb,
+    b: 0xee,
+  },
+  colorous::Color {
+    r: 0x00,
+    g: 0x99,
+    b: 0x88,
+  },
+  colorous::Color {
+    r: 0xee,
+    g: 0x77,
+    b: 0x33,
+  },
+  colorous::Color {
+    r: 0xcc,
+    g: 0x33,
+    b: 0x11,
+  },
+  colorous::Color {
+    r: 0xee,
+    g: 0x33,
+    b: 0x77,
+  },
+  colorous::Color {
+    r: 0xbb,
+    g: 0xbb,
+    b: 0xbb,
+  },
+];
+
+/// The CIEDE2000 difference below which two colors are hard to tell apart at
+/// a glance, particularly as small glyphs on a terminal.
+pub const MIN_DELTA_E: f32 = 8.0;
+
+/// Returns the perceptual difference between two colors.
+pub fn delta_e(a: Srgb<u8>, b: Srgb<u8>) -> f32 {
+  let a: Lab = a.into_format::<f32>().into_color();
+  let b: Lab = b.into_format::<f32>().into_color();
+  a.get_color_difference(&b)
+}
+
+/// How many colors a terminal can display.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Depth {
+  None,
+  Ansi16,
+  Xterm256,
+  True,
+}
+
+/// When to emit colors, as chosen with `--color`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum When {
+  #[default]
+  Auto,
+  Always,
+  Never,
+  /// Always emit colors, at this depth.
+  Force(Depth),
+}
+
+impl argh::FromArgValue for When {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    match value.to_lowercase().as_str() {
+      "auto" => Ok(Self::Auto),
+      "always" | "true" => Ok(Self::Always),
+      "never" | "false" | "none" => Ok(Self::Never),
+      "16" => Ok(Self::Force(Depth::Ansi16)),
+      "256" => Ok(Self::Force(Depth::Xterm256)),
+      "truecolor" | "24bit" => Ok(Self::Force(Depth::True)),
+      _ => Err(
+        "expected `auto`, `always`, `never`, `16`, `256`, or `truecolor`"
+          .into(),
+      ),
+    }
+  }
+}
+
+impl Depth {
+  /// Works out the color depth to use, based on `when`, whether the output is
+  /// a terminal, and the environment.
+  ///
+  /// This follows the usual conventions: `NO_COLOR` disables color and
+  /// `CLICOLOR_FORCE` enables it even when not writing to a terminal; the
+  /// depth comes from `COLORTERM`, then terminfo, then `TERM` itself.
+  pub fn detect(mut when: When, is_tty: bool) -> Depth {
+    let var = |name| env::var_os(name).filter(|v| !v.is_empty());
+    if when == When::Auto {
+      if var("NO_COLOR").is_some() {
+        return Depth::None;
+      }
+      if var("CLICOLOR_FORCE").is_some_and(|v| v != "0") {
+        when = When::Always;
+      } else if !is_tty {
+        return Depth::None;
+      }
+    }
+    match when {
+      When::Never => return Depth::None,
+      When::Force(depth) => return depth,
+      _ => {}
+    }
+
+    if let Some(colorterm) = var("COLORTERM") {
+      if colorterm == "truecolor" || colorterm == "24bit" {
+        return Depth::True;
+      }
+    }
+
+    let Some(term) = var("TERM") else {
+      // Without a TERM, assume something modern, like Windows Terminal.
+      return Depth::Xterm256;
+    };
+    let term = term.to_string_lossy();
+    if term == "dumb" {
+      return match when {
+        When::Auto => Depth::None,
+        _ => Depth::Ansi16,
+      };
+    }
+
+    match terminfo_colors(&term) {
+      Some(n) if n >= 1 << 24 => Depth::True,
+      Some(n) if n >= 256 => Depth::Xterm256,
+      Some(n) if n >= 8 => Depth::Ansi16,
+      Some(_) if when == When::Auto => Depth::None,
+      Some(_) => Depth::Ansi16,
+      None if term.contains("256color") => Depth::Xterm256,
+      None => Depth::Ansi16,
+    }
+  }
+}
+
+/// Looks up the `colors` capability for `term` in the terminfo database. An
+/// entry without one can't do color at all.
+fn terminfo_colors(term: &str) -> Option<u32> {
+  let first = term.chars().next()?;
+  let mut dirs = Vec::new();
+  if let Some(dir) = env::var_os("TERMINFO") {
+    dirs.push(PathBuf::from(dir));
+  }
+  if let Some(home) = env::var_os("HOME") {
+    dirs.push(Path::new(&home).join(".terminfo"));
+  }
+  if let Some(list) = env::var_os("TERMINFO_DIRS") {
+    dirs.extend(env::split_paths(&list));
+  }
+  dirs.extend(
+    ["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].map(Into::into),
+  );
+
+  let data = dirs.iter().find_map(|dir| {
+    // Entries live under either their first letter, or its hex code.
+    let by_char = dir.join(first.to_string()).join(term);
+    let by_hex = dir.join(format!("{:x}", first as u32)).join(term);
+    fs::read(by_char).or_else(|_| fs::read(by_hex)).ok()
+  })?;
+  parse_terminfo_colors(&data)
+}
+
+/// Reads the `colors` capability out of a compiled terminfo entry, which may
+/// be in the legacy format or the one with 32-bit numbers. See term(5) for
+/// both.
+fn parse_terminfo_colors(data: &[u8]) -> Option<u32> {
+  let word = |i: usize| -> Option<u16> {
+    Some(u16::from_le_bytes(
+      data.get(i * 2..i * 2 + 2)?.try_into().ok()?,
+    ))
+  };
+  let num_size = match word(0)? {
+    0o432 => 2,
+    0o1036 => 4,
+    _ => return None,
+  };
+  let (names, bools, nums) =
+    (word(1)? as usize, word(2)? as usize, word(3)? as usize);
+
+  // `colors` is the number at index 13.
+  const COLORS: usize = 13;
+  if nums <= COLORS {
+    return Some(0);
+  }
+  let mut start = 12 + names + bools;
+  start += start % 2;
+  start += COLORS * num_size;
+  let bytes = data.get(start..start + num_size)?;
+  let n = match num_size {
+    2 => i16::from_le_bytes(bytes.try_into().ok()?) as i32,
+    _ => i32::from_le_bytes(bytes.try_into().ok()?),
+  };
+  Some(u32::try_from(n).unwrap_or(0))
+}
+
+/// Returns whether `color` is light enough that black text on top of it is
+/// easier to read than white text.
+pub fn is_light(color: Srgb<u8>) -> bool {
+  let lin: LinSrgb = color.into_format::<f32>().into_linear();
+  let luma = 0.2126 * lin.red + 0.7152 * lin.green + 0.0722 * lin.blue;
+  // This is where the contrast ratios against black and white are equal.
+  luma > 0.179
+}
+
+/// Text attributes that can be on a terminal.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Style {
+  pub bold: bool,
+  pub dim: bool,
+  pub underline: bool,
+  /// Drawn with a background color by the renderer, rather than by
+  /// [`Style::apply`].
+  pub highlight: bool,
+}
+
+impl Style {
+  /// Decodes a style from a formula result: 1 is bold, 2 is dim, 4 is
+  /// underline, and 8 is highlight.
+  pub fn from_bits(bits: u64) -> Self {
+    Self {
+      bold: bits & 1 != 0,
+      dim: bits & 2 != 0,
+      underline: bits & 4 != 0,
+      highlight: bits & 8 != 0,
+    }
+  }
+
+  /// Sets the text attributes on `out` to this style.
+  pub fn apply(self, out: &mut (impl io::Write + ?Sized)) -> io::Result<()> {
+    write!(out, "\x1b[22;24m")?;
+    if self.bold {
+      write!(out, "\x1b[1m")?;
+    }
+    if self.dim {
+      write!(out, "\x1b[2m")?;
+    }
+    if self.underline {
+      write!(out, "\x1b[4m")?;
+    }
+    Ok(())
+  }
+}
+
 /// A color that can be on a terminal.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum TermColor {
@@ -129,11 +581,25 @@ impl TermColor {
       Self::Reset => write!(out, "\x1b[49m"),
     }
   }
+
+  /// Returns what this color looks like, or `None` for the terminal's
+  /// default color.
+  pub fn rgb(self) -> Option<Srgb<u8>> {
+    let entry = match self {
+      Self::Dark(c) => ANSI16_PALETTE.get((c & 7) as usize),
+      Self::Bright(c) => ANSI16_PALETTE.get((c & 7) as usize + 8),
+      Self::Index(i) => i.checked_sub(16).and_then(|i| XTERM256_PALETTE.get(i)),
+      Self::Rgb(c) => return Some(c),
+      Self::Reset => None,
+    };
+    entry.map(|&rgb| Srgb::from_components(rgb))
+  }
 }
 
-/// The `xterm-256color` colors, as a palette.
-pub const XTERM256_PALETTE: &[(u8, u8, u8)] = &[
-  /*(0, 0, 0),
+/// The sixteen system colors, as a palette. The first eight are the
+/// [`TermColor::Dark`] colors, and the rest are [`TermColor::Bright`].
+pub const ANSI16_PALETTE: &[(u8, u8, u8)] = &[
+  (0, 0, 0),
   (128, 0, 0),
   (0, 128, 0),
   (128, 128, 0),
@@ -148,7 +614,12 @@ pub const XTERM256_PALETTE: &[(u8, u8, u8)] = &[
   (0, 0, 255),
   (255, 0, 255),
   (0, 255, 255),
-  (255, 255, 255),*/
+  (255, 255, 255),
+];
+
+/// The `xterm-256color` colors after the system colors, as a palette. Index
+/// `i` in this palette is [`TermColor::Index`] `i + 16`.
+pub const XTERM256_PALETTE: &[(u8, u8, u8)] = &[
   (0, 0, 0),
   (0, 0, 95),
   (0, 0, 135),
@@ -390,3 +861,98 @@ pub const XTERM256_PALETTE: &[(u8, u8, u8)] = &[
   (228, 228, 228),
   (238, 238, 238),
 ];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Builds a compiled terminfo entry with the given numbers, in the legacy
+  /// format if `wide` is false.
+  fn terminfo(wide: bool, names: &str, bools: usize, nums: &[i32]) -> Vec<u8> {
+    let magic: u16 = if wide { 0o1036 } else { 0o432 };
+    let mut data = Vec::new();
+    let header = [
+      magic,
+      names.len() as u16 + 1,
+      bools as u16,
+      nums.len() as u16,
+    ];
+    for word in header.into_iter().chain([0, 0]) {
+      data.extend(word.to_le_bytes());
+    }
+    data.extend(names.as_bytes());
+    data.push(0);
+    data.extend(vec![1; bools]);
+    if data.len() % 2 != 0 {
+      data.push(0);
+    }
+    for &n in nums {
+      match wide {
+        true => data.extend(n.to_le_bytes()),
+        false => data.extend((n as i16).to_le_bytes()),
+      }
+    }
+    data
+  }
+
+  /// Numbers up to and including `colors`, at index 13.
+  fn nums(colors: i32) -> Vec<i32> {
+    let mut nums = vec![-1; 14];
+    nums[0] = 80;
+    nums[13] = colors;
+    nums
+  }
+
+  #[test]
+  fn terminfo_colors() {
+    let parse = parse_terminfo_colors;
+    let legacy = terminfo(false, "xterm-256color|xterm", 38, &nums(256));
+    assert_eq!(parse(&legacy), Some(256));
+    let wide = terminfo(true, "xterm-direct|xterm", 38, &nums(1 << 24));
+    assert_eq!(parse(&wide), Some(1 << 24));
+
+    // Names and booleans that end on an odd byte are padded before the
+    // numbers.
+    assert_eq!(parse(&terminfo(false, "ab", 3, &nums(8))), Some(8));
+    assert_eq!(parse(&terminfo(true, "ab", 4, &nums(88))), Some(88));
+
+    // Absent and cancelled numbers, and entries without enough numbers to
+    // have `colors` at all, can't do color.
+    assert_eq!(parse(&terminfo(false, "vt100", 0, &nums(-1))), Some(0));
+    assert_eq!(parse(&terminfo(true, "vt100", 0, &nums(-2))), Some(0));
+    assert_eq!(parse(&terminfo(false, "vt100", 0, &[80, 24])), Some(0));
+
+    let mut bad = legacy.clone();
+    bad[0] = 0;
+    assert_eq!(parse(&bad), None);
+    assert_eq!(parse(b""), None);
+  }
+
+  /// Truncated entries are rejected, without reading past the end.
+  #[test]
+  fn terminfo_truncated() {
+    for wide in [false, true] {
+      let colors = if wide { 1 << 24 } else { 256 };
+      let data = terminfo(wide, "xterm|xterm", 5, &nums(colors));
+      for len in 0..data.len() {
+        assert_eq!(parse_terminfo_colors(&data[..len]), None, "{len} bytes");
+      }
+
+      // Header counts that run far past the end of the entry.
+      let mut huge = data.clone();
+      huge[2..8].copy_from_slice(&[0xff; 6]);
+      assert_eq!(parse_terminfo_colors(&huge), None);
+    }
+  }
+
+  /// Colors forced on or off don't depend on the environment.
+  #[test]
+  fn detect_forced() {
+    for is_tty in [false, true] {
+      assert_eq!(Depth::detect(When::Never, is_tty), Depth::None);
+      for depth in [Depth::Ansi16, Depth::Xterm256, Depth::True] {
+        assert_eq!(Depth::detect(When::Force(depth), is_tty), depth);
+      }
+    }
+  }
+}
@@ -0,0 +1,225 @@
+// Copyright 2022 Miguel Young de la Sota
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compressed input formats.
+
+use std::cell::Cell;
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::rc::Rc;
+
+/// A compression format that inputs are decompressed from.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Format {
+  Gzip,
+  Zstd,
+  Xz,
+  Bzip2,
+}
+
+impl Format {
+  /// The longest magic number of any format.
+  pub const MAGIC_LEN: usize = 10;
+
+  /// Works out the format of a file that starts with `head`, if it's
+  /// compressed at all.
+  ///
+  /// Magic numbers are matched in full, including the gzip compression
+  /// method and the bzip2 block size and block header, so that ordinary
+  /// files that happen to start with the same few bytes aren't mistaken for
+  /// compressed ones.
+  pub fn detect(head: &[u8]) -> Option<Format> {
+    if head.starts_with(&[0x1f, 0x8b, 0x08]) {
+      Some(Self::Gzip)
+    } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+      Some(Self::Zstd)
+    } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+      Some(Self::Xz)
+    } else if head.len() >= 10
+      && head.starts_with(b"BZh")
+      && (b'1'..=b'9').contains(&head[3])
+      && head[4..10] == [0x31, 0x41, 0x59, 0x26, 0x53, 0x59]
+    {
+      Some(Self::Bzip2)
+    } else {
+      None
+    }
+  }
+
+  /// Wraps `r` in a decoder for this format. Files made of several
+  /// compressed streams one after the other are decoded in full.
+  ///
+  /// Formats that this build doesn't support are an error.
+  ///
+  /// Decoders only take as much of `r` as they've decoded, so [`Counted`]
+  /// can tell how far into the compressed input they are.
+  pub fn decoder<'a>(
+    self,
+    r: impl BufRead + 'a,
+  ) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match self {
+      Self::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(r)),
+      #[cfg(feature = "zstd")]
+      Self::Zstd => Box::new(zstd::Decoder::with_buffer(r)?),
+      #[cfg(feature = "xz")]
+      Self::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(r)),
+      #[cfg(feature = "bzip2")]
+      Self::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(r)),
+      #[allow(unreachable_patterns)]
+      format => {
+        return Err(io::Error::new(
+          io::ErrorKind::Unsupported,
+          format!(
+            "input is {format:?}-compressed, but this build can't \
+             decompress that; use --raw to dump it as it is"
+          ),
+        ))
+      }
+    })
+  }
+
+  /// Checks whether `r` really is in this format, by decoding the start of
+  /// it. Returns everything read from `r` to find out, so that it can be
+  /// read again.
+  pub fn probe(self, r: &mut dyn Read) -> io::Result<(bool, Vec<u8>)> {
+    let mut seen = Vec::new();
+    let mut decoder = self.decoder(io::BufReader::new(Recorded {
+      inner: r,
+      seen: &mut seen,
+    }))?;
+    let ok = loop {
+      match decoder.read(&mut [0; 1]) {
+        Ok(_) => break true,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(_) => break false,
+      }
+    };
+    drop(decoder);
+    Ok((ok, seen))
+  }
+}
+
+/// A reader that keeps a copy of everything read out of it.
+struct Recorded<'a> {
+  inner: &'a mut dyn Read,
+  seen: &'a mut Vec<u8>,
+}
+
+impl Read for Recorded<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let len = self.inner.read(buf)?;
+    self.seen.extend_from_slice(&buf[..len]);
+    Ok(len)
+  }
+}
+
+/// A buffered reader that counts how many bytes have been consumed out of
+/// it, so that we can tell how far into the compressed input a decoder has
+/// got. Bytes that are buffered, but that the decoder hasn't used yet, don't
+/// count.
+pub struct Counted<R> {
+  inner: io::BufReader<R>,
+  count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Counted<R> {
+  pub fn new(inner: R, count: Rc<Cell<u64>>) -> Self {
+    Self {
+      inner: io::BufReader::new(inner),
+      count,
+    }
+  }
+}
+
+impl<R: Read> Read for Counted<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let len = self.inner.read(buf)?;
+    self.count.set(self.count.get() + len as u64);
+    Ok(len)
+  }
+}
+
+impl<R: Read> BufRead for Counted<R> {
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    self.inner.fill_buf()
+  }
+
+  fn consume(&mut self, len: usize) {
+    self.inner.consume(len);
+    self.count.set(self.count.get() + len as u64);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn detect() {
+    let detect = Format::detect;
+    assert_eq!(detect(&[0x1f, 0x8b, 0x08, 0x00]), Some(Format::Gzip));
+    assert_eq!(detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x24]), Some(Format::Zstd));
+    assert_eq!(detect(b"\xfd7zXZ\0\0\x04"), Some(Format::Xz));
+    assert_eq!(detect(b"BZh91AY&SY\x01"), Some(Format::Bzip2));
+
+    // Only whole magic numbers count.
+    assert_eq!(detect(&[0x1f, 0x8b]), None);
+    assert_eq!(detect(&[0x1f, 0x8b, 0x07]), None);
+    assert_eq!(detect(b"\xfd7zXZ"), None);
+    assert_eq!(detect(b"BZh9"), None);
+    assert_eq!(detect(b"BZh01AY&SY"), None);
+    assert_eq!(detect(b"BZh9 and some text"), None);
+    assert_eq!(detect(b""), None);
+  }
+
+  #[test]
+  fn probe() {
+    let mut gzip = Vec::new();
+    let mut w = flate2::write::GzEncoder::new(&mut gzip, Default::default());
+    io::Write::write_all(&mut w, b"hello").unwrap();
+    w.finish().unwrap();
+
+    let (ok, seen) = Format::Gzip.probe(&mut &gzip[..]).unwrap();
+    assert!(ok);
+    assert_eq!(seen, gzip);
+
+    // Something with the right magic number, but that doesn't decode, is
+    // turned down, and everything read from it is handed back.
+    let fake = b"\x1f\x8b\x08 is not really gzip";
+    let (ok, seen) = Format::Gzip.probe(&mut &fake[..]).unwrap();
+    assert!(!ok);
+    assert_eq!(seen, fake);
+  }
+
+  /// Decoders only count the compressed bytes they've used.
+  #[test]
+  fn counted() {
+    let data = (0..4096).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>();
+    let mut gzip = Vec::new();
+    let mut w = flate2::write::GzEncoder::new(&mut gzip, Default::default());
+    io::Write::write_all(&mut w, &data).unwrap();
+    w.finish().unwrap();
+
+    let count = Rc::new(Cell::new(0));
+    let mut decoder = Format::Gzip
+      .decoder(Counted::new(&gzip[..], count.clone()))
+      .unwrap();
+    assert!(count.get() < 16, "{} used before reading", count.get());
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out).unwrap();
+    assert_eq!(out, data);
+    assert_eq!(count.get(), gzip.len() as u64);
+  }
+}
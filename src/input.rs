@@ -0,0 +1,686 @@
+// Copyright 2022 Miguel Young de la Sota
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Input sources, and the parts of them to dump.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::compress;
+use crate::compress::Counted;
+
+/// Something to read bytes from, which keeps track of how far into it we
+/// are.
+pub struct Input {
+  source: Source,
+  pos: u64,
+  size: Option<u64>,
+}
+
+enum Source {
+  File(File),
+  Stream(Box<dyn Read>),
+  /// Several inputs one after another, and the index of the one being read.
+  Concat(Vec<Input>, usize),
+  Decoded(Decoded),
+  Memory(io::Cursor<Vec<u8>>),
+}
+
+/// A compressed input, which is decompressed as it's read.
+struct Decoded {
+  format: compress::Format,
+  decoder: Box<dyn Read>,
+  /// How much of the compressed input the decoder has used.
+  consumed: Rc<Cell<u64>>,
+  /// The compressed file, if there is one, which can be decompressed again
+  /// from the start to seek backwards.
+  file: Option<File>,
+}
+
+impl Decoded {
+  /// Starts decompressing the file over from the beginning.
+  fn restart(&mut self) -> io::Result<()> {
+    let Some(file) = &mut self.file else {
+      return Ok(());
+    };
+    file.rewind()?;
+    self.consumed.set(0);
+    self.decoder = self
+      .format
+      .decoder(Counted::new(file.try_clone()?, self.consumed.clone()))?;
+    Ok(())
+  }
+}
+
+impl Input {
+  /// Wraps a file. Regular files can be seeked around in; anything else is
+  /// treated like a stream.
+  pub fn file(mut file: File) -> io::Result<Input> {
+    let meta = file.metadata()?;
+    if !meta.is_file() {
+      return Ok(Self::stream(file));
+    }
+    Ok(Input {
+      pos: file.stream_position()?,
+      size: Some(meta.len()),
+      source: Source::File(file),
+    })
+  }
+
+  /// Wraps a stream, which can only be read front to back.
+  pub fn stream(r: impl Read + 'static) -> Input {
+    Input {
+      source: Source::Stream(Box::new(r)),
+      pos: 0,
+      size: None,
+    }
+  }
+
+  /// Wraps bytes that are already in memory.
+  pub fn memory(data: Vec<u8>) -> Input {
+    Input {
+      size: Some(data.len() as u64),
+      source: Source::Memory(io::Cursor::new(data)),
+      pos: 0,
+    }
+  }
+
+  /// Joins several inputs into one. The result can be seeked around in only
+  /// if all of them can.
+  pub fn concat(parts: Vec<Input>) -> Input {
+    let size = parts.iter().map(Input::size).sum();
+    Input {
+      source: Source::Concat(parts, 0),
+      pos: 0,
+      size,
+    }
+  }
+
+  /// If this input starts with the magic number of a compressed format, and
+  /// the start of it decodes as that format, returns an input that
+  /// decompresses it on the fly. Otherwise, returns it unchanged.
+  pub fn decompress(self) -> io::Result<Input> {
+    let (format, file, inner): (_, _, Box<dyn Read>) = match self.source {
+      Source::File(mut file) => {
+        let mut head = Vec::new();
+        (&mut file)
+          .take(compress::Format::MAGIC_LEN as u64)
+          .read_to_end(&mut head)?;
+        file.seek(io::SeekFrom::Start(self.pos))?;
+        let format = compress::Format::detect(&head);
+        let format = match format {
+          Some(format) => {
+            let (ok, _) = format.probe(&mut file)?;
+            file.seek(io::SeekFrom::Start(self.pos))?;
+            Some(format).filter(|_| ok)
+          }
+          None => None,
+        };
+        let Some(format) = format else {
+          return Ok(Input {
+            source: Source::File(file),
+            ..self
+          });
+        };
+        (format, Some(file.try_clone()?), Box::new(file))
+      }
+      Source::Stream(mut r) => {
+        // Pipes can hand over fewer bytes than asked for, so we keep reading
+        // until there are enough to tell.
+        let mut head = Vec::new();
+        (&mut r)
+          .take(compress::Format::MAGIC_LEN as u64)
+          .read_to_end(&mut head)?;
+        let mut ok = false;
+        let format = compress::Format::detect(&head);
+        if let Some(format) = format {
+          // Whatever the probe reads, including the head, is read again
+          // afterwards.
+          let mut rest = io::Cursor::new(&head).chain(&mut r);
+          let (decodes, mut seen) = format.probe(&mut rest)?;
+          if seen.len() < head.len() {
+            seen.extend_from_slice(&head[seen.len()..]);
+          }
+          head = seen;
+          ok = decodes;
+        }
+        let r = Box::new(io::Cursor::new(head).chain(r));
+        let format = format.filter(|_| ok);
+        let Some(format) = format else {
+          return Ok(Input {
+            source: Source::Stream(r),
+            ..self
+          });
+        };
+        (format, None, r)
+      }
+      source => return Ok(Input { source, ..self }),
+    };
+
+    let consumed = Rc::new(Cell::new(0));
+    let decoder = format.decoder(Counted::new(inner, consumed.clone()))?;
+    Ok(Input {
+      source: Source::Decoded(Decoded {
+        format,
+        decoder,
+        consumed,
+        file,
+      }),
+      pos: 0,
+      size: None,
+    })
+  }
+
+  /// Returns how much of the compressed input the decompressor has used, if
+  /// this input is being decompressed.
+  pub fn compressed_pos(&self) -> Option<u64> {
+    match &self.source {
+      Source::Decoded(d) => Some(d.consumed.get()),
+      _ => None,
+    }
+  }
+
+  /// Returns the size of the input, if it can be known ahead of time.
+  pub fn size(&self) -> Option<u64> {
+    self.size
+  }
+
+  /// Returns how far into the input we are.
+  pub fn pos(&self) -> u64 {
+    self.pos
+  }
+
+  /// Returns whether this input can be seeked backwards.
+  pub fn is_seekable(&self) -> bool {
+    match &self.source {
+      Source::File(_) | Source::Memory(_) => true,
+      Source::Stream(_) => false,
+      Source::Concat(parts, _) => {
+        parts.iter().all(|p| p.is_seekable() && p.size.is_some())
+      }
+      Source::Decoded(d) => d.file.is_some(),
+    }
+  }
+
+  /// Whether more may be written to the end of this input after it's been
+  /// read, as with a log file. Streams are done once they run out.
+  pub fn can_grow(&self) -> bool {
+    matches!(self.source, Source::File(_))
+  }
+
+  /// Moves to `pos`. Streams can only skip forwards, by reading and throwing
+  /// away everything in between. Compressed files skip forwards in the same
+  /// way, and go backwards by decompressing from the start again.
+  pub fn seek_to(&mut self, pos: u64) -> io::Result<()> {
+    let seekable = self.is_seekable();
+    match &mut self.source {
+      Source::File(file) => {
+        file.seek(io::SeekFrom::Start(pos))?;
+        self.pos = pos;
+      }
+      Source::Memory(data) => {
+        data.set_position(pos);
+        self.pos = pos;
+      }
+      Source::Concat(parts, idx) if seekable => {
+        // Every part is a file, so we can find the one `pos` falls in.
+        let last = parts.len().saturating_sub(1);
+        let mut start = 0;
+        for (i, part) in parts.iter_mut().enumerate() {
+          let end = start + part.size.unwrap_or(0);
+          if i == last || pos < end {
+            part.seek_to(pos - start)?;
+            *idx = i;
+            break;
+          }
+          start = end;
+        }
+        self.pos = pos;
+      }
+      Source::Decoded(d) if seekable && pos < self.pos => {
+        d.restart()?;
+        self.pos = 0;
+        return self.seek_to(pos);
+      }
+      _ if pos >= self.pos => {
+        let skip = pos - self.pos;
+        io::copy(&mut self.by_ref().take(skip), &mut io::sink())?;
+        if self.pos < pos {
+          return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("can't seek to {pos:#x}; input ends at {:#x}", self.pos),
+          ));
+        }
+      }
+      _ => {
+        return Err(io::Error::new(
+          io::ErrorKind::Unsupported,
+          format!(
+            "can't go back to {pos:#x} in a stream, which is already at {:#x}",
+            self.pos
+          ),
+        ))
+      }
+    }
+    Ok(())
+  }
+
+  /// Reads a stream to the end, keeping only the last `len` bytes, so that
+  /// positions counted from the end can be found. Afterwards, the size of the
+  /// input is known and it is positioned at the start of the kept bytes.
+  ///
+  /// Compressed files are decompressed once just to find their size, since
+  /// they can be decompressed again to go back. Nothing needs doing to other
+  /// files, whose size is known already.
+  pub fn buffer_tail(&mut self, len: u64) -> io::Result<()> {
+    if self.size.is_some() {
+      return Ok(());
+    }
+    if self.is_seekable() {
+      io::copy(self, &mut io::sink())?;
+      self.size = Some(self.pos);
+      return Ok(());
+    }
+
+    let mut tail = VecDeque::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+      let n = match self.read(&mut buf) {
+        Ok(0) => break,
+        Ok(n) => n,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(e) => return Err(e),
+      };
+      tail.extend(&buf[..n]);
+      let excess = (tail.len() as u64).saturating_sub(len);
+      tail.drain(..excess as usize);
+    }
+
+    let size = self.pos;
+    self.pos = size - tail.len() as u64;
+    self.size = Some(size);
+    self.source = Source::Stream(Box::new(io::Cursor::new(Vec::from(tail))));
+    Ok(())
+  }
+}
+
+impl Read for Input {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let len = match &mut self.source {
+      Source::File(file) => file.read(buf)?,
+      Source::Stream(r) => r.read(buf)?,
+      Source::Decoded(d) => d.decoder.read(buf)?,
+      Source::Memory(data) => data.read(buf)?,
+      Source::Concat(parts, idx) => loop {
+        let Some(part) = parts.get_mut(*idx) else {
+          break 0;
+        };
+        match part.read(buf)? {
+          // Stay on the last part, in case it grows.
+          0 if *idx + 1 < parts.len() => {
+            *idx += 1;
+            parts[*idx].seek_to(0)?;
+          }
+          n => break n,
+        }
+      },
+    };
+    self.pos += len as u64;
+    Ok(len)
+  }
+}
+
+/// Seeking is done with [`Input::seek_to()`], so streams can only go
+/// forwards, and finding the end of a compressed file decompresses all of it.
+impl Seek for Input {
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    let pos = match pos {
+      io::SeekFrom::Start(n) => Some(n),
+      io::SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+      io::SeekFrom::End(n) => {
+        self.buffer_tail(0)?;
+        self.size.and_then(|size| size.checked_add_signed(n))
+      }
+    };
+    let pos = pos.ok_or_else(|| {
+      io::Error::new(io::ErrorKind::InvalidInput, "seek out of bounds")
+    })?;
+    self.seek_to(pos)?;
+    Ok(pos)
+  }
+}
+
+/// A position in the input: either an offset from the start, or a number of
+/// bytes back from the end.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Pos {
+  Start(u64),
+  End(u64),
+}
+
+/// Where a [`Span`] ends.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SpanEnd {
+  /// At the end of the input.
+  Open,
+  /// At this position.
+  At(Pos),
+  /// This many bytes after the start.
+  Len(u64),
+}
+
+/// A part of the input to dump, as written on the command line.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Span {
+  pub start: Pos,
+  pub end: SpanEnd,
+}
+
+impl Span {
+  /// Returns how many bytes back from the end of the input this span needs
+  /// to look, if any.
+  pub fn lookback(self) -> Option<u64> {
+    let start = match self.start {
+      Pos::End(n) => Some(n),
+      Pos::Start(_) => None,
+    };
+    let end = match self.end {
+      SpanEnd::At(Pos::End(n)) => Some(n),
+      _ => None,
+    };
+    start.max(end)
+  }
+
+  /// Works out the byte range this span covers, in an input of `size` bytes.
+  /// An open end is `u64::MAX`.
+  pub fn resolve(self, size: Option<u64>) -> Result<Range<u64>, String> {
+    let pos = |pos| match (pos, size) {
+      (Pos::Start(n), _) => Ok(n),
+      (Pos::End(n), Some(size)) => Ok(size.saturating_sub(n)),
+      (Pos::End(n), None) => Err(format!(
+        "can't find the position {n} bytes from the end of an input of \
+         unknown size"
+      )),
+    };
+    let start = match (self.start, size) {
+      (Pos::Start(n), Some(size)) if n > size => {
+        return Err(format!(
+          "can't seek to {n:#x}; input is only {size:#x} bytes long"
+        ))
+      }
+      (Pos::End(n), Some(size)) if n > size => {
+        return Err(format!(
+          "can't seek back {n:#x} bytes; input is only {size:#x} bytes long"
+        ))
+      }
+      (start, _) => pos(start)?,
+    };
+    let end = match self.end {
+      SpanEnd::Open => u64::MAX,
+      SpanEnd::At(end) => pos(end)?,
+      SpanEnd::Len(len) => start.saturating_add(len),
+    };
+    if end < start {
+      return Err(format!("range {start:#x}..{end:#x} ends before it starts"));
+    }
+    Ok(start..end)
+  }
+}
+
+/// Splits `range` up at the starts and ends of the ranges in `at`, so that
+/// each piece lies either entirely inside or entirely outside of each of
+/// them.
+pub fn split(range: Range<u64>, at: &[Range<u64>]) -> Vec<Range<u64>> {
+  let mut cuts = at
+    .iter()
+    .flat_map(|r| [r.start, r.end])
+    .filter(|&cut| range.start < cut && cut < range.end)
+    .collect::<Vec<_>>();
+  cuts.push(range.start);
+  cuts.push(range.end);
+  cuts.sort();
+  cuts.dedup();
+  cuts.windows(2).map(|w| w[0]..w[1]).collect()
+}
+
+/// Parses a range of the input written as `start..end` or `start+len`, as in
+/// address maps and annotation files.
+pub fn parse_range(range: &str) -> Result<Range<u64>, String> {
+  if let Some((start, end)) = range.split_once("..") {
+    let (start, end) = (crate::parse_int(start)?, crate::parse_int(end)?);
+    if start > end {
+      return Err(format!("{range} ends before it starts"));
+    }
+    Ok(start..end)
+  } else if let Some((start, len)) = range.split_once('+') {
+    let start = crate::parse_int(start)?;
+    Ok(start..start.saturating_add(crate::parse_int(len)?))
+  } else {
+    Err(format!("expected `start..end` or `start+len`, got {range}"))
+  }
+}
+
+/// A table of where parts of the input are mapped in memory, which rows in
+/// those parts are labeled with.
+///
+/// It's written one mapping per line, as a range of the input in the same
+/// syntax as `--range` followed by the address it's mapped to:
+///
+/// ```text
+/// # Lines starting with a hash are comments.
+/// 0x0..0x1000     0x08000000
+/// 0x1000+0x400    0x20000000
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AddressMap {
+  pub entries: Vec<(Range<u64>, u64)>,
+}
+
+impl AddressMap {
+  /// Parses an address map file.
+  pub fn parse(src: &str) -> Result<AddressMap, String> {
+    let mut entries = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let err = |e: String| format!("line {}: {e}", i + 1);
+      let (range, addr) = line
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| err("expected `range address`".into()))?;
+      let range = parse_range(range).map_err(err)?;
+      let addr = crate::parse_int(addr.trim()).map_err(err)?;
+      let last = range.end.saturating_sub(range.start.saturating_add(1));
+      if addr.checked_add(last).is_none() {
+        return Err(err(format!(
+          "{range:#x?} at {addr:#x} runs past the top of memory"
+        )));
+      }
+      entries.push((range, addr));
+    }
+    Ok(AddressMap { entries })
+  }
+
+  /// Returns the address that `offset` into the input is mapped to, if any.
+  pub fn get(&self, offset: u64) -> Option<u64> {
+    self
+      .entries
+      .iter()
+      .find(|(range, _)| range.contains(&offset))
+      .map(|(range, addr)| addr + (offset - range.start))
+  }
+}
+
+impl argh::FromArgValue for AddressMap {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    let src =
+      std::fs::read_to_string(value).map_err(|e| format!("{value}: {e}"))?;
+    Self::parse(&src).map_err(|e| format!("{value}: {e}"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn span(start: Pos, end: SpanEnd) -> Span {
+    Span { start, end }
+  }
+
+  #[test]
+  fn resolve() {
+    use Pos::*;
+    use SpanEnd::*;
+    let size = Some(0x100);
+    assert_eq!(span(Start(0x10), Open).resolve(size), Ok(0x10..u64::MAX));
+    assert_eq!(span(Start(0x10), Len(0x20)).resolve(size), Ok(0x10..0x30));
+    assert_eq!(
+      span(Start(0x10), At(Start(0x18))).resolve(size),
+      Ok(0x10..0x18)
+    );
+    // Negative positions count back from the end.
+    assert_eq!(span(End(0x10), Open).resolve(size), Ok(0xf0..u64::MAX));
+    assert_eq!(span(Start(0), At(End(0x10))).resolve(size), Ok(0..0xf0));
+    assert_eq!(span(End(0x10), At(End(0x8))).resolve(size), Ok(0xf0..0xf8));
+    assert_eq!(span(End(0x100), Len(1)).resolve(size), Ok(0..1));
+    // Lengths and ends can run past the end of the input, and are cut short
+    // when it's read.
+    assert_eq!(span(Start(0xff), Len(0x10)).resolve(size), Ok(0xff..0x10f));
+    assert_eq!(span(Start(1), Len(u64::MAX)).resolve(size), Ok(1..u64::MAX));
+    assert_eq!(span(Start(0x100), Open).resolve(size), Ok(0x100..u64::MAX));
+    // Without a size, only positions from the start work.
+    assert_eq!(span(Start(0x10), Len(4)).resolve(None), Ok(0x10..0x14));
+    assert_eq!(
+      span(Start(u64::MAX), Open).resolve(None),
+      Ok(u64::MAX..u64::MAX)
+    );
+  }
+
+  #[test]
+  fn resolve_errors() {
+    use Pos::*;
+    use SpanEnd::*;
+    let err = |s: Span, size| s.resolve(size).unwrap_err();
+    assert_eq!(
+      err(span(Start(0x101), Open), Some(0x100)),
+      "can't seek to 0x101; input is only 0x100 bytes long"
+    );
+    assert_eq!(
+      err(span(End(0x101), Open), Some(0x100)),
+      "can't seek back 0x101 bytes; input is only 0x100 bytes long"
+    );
+    assert_eq!(
+      err(span(End(4), Open), None),
+      "can't find the position 4 bytes from the end of an input of unknown \
+       size"
+    );
+    assert_eq!(
+      err(span(Start(0), At(End(4))), None),
+      "can't find the position 4 bytes from the end of an input of unknown \
+       size"
+    );
+    assert_eq!(
+      err(span(Start(0x20), At(Start(0x10))), Some(0x100)),
+      "range 0x20..0x10 ends before it starts"
+    );
+    assert_eq!(
+      err(span(End(0x10), At(End(0x20))), Some(0x100)),
+      "range 0xf0..0xe0 ends before it starts"
+    );
+  }
+
+  #[test]
+  fn lookback() {
+    use Pos::*;
+    use SpanEnd::*;
+    assert_eq!(span(Start(4), Open).lookback(), None);
+    assert_eq!(span(End(4), Len(2)).lookback(), Some(4));
+    assert_eq!(span(Start(0), At(End(8))).lookback(), Some(8));
+    assert_eq!(span(End(4), At(End(2))).lookback(), Some(4));
+  }
+
+  #[test]
+  fn ranges() {
+    assert_eq!(parse_range("0x10..0x20"), Ok(0x10..0x20));
+    assert_eq!(parse_range("16+16"), Ok(16..32));
+    assert_eq!(parse_range(" 4 .. 4 "), Ok(4..4));
+    assert_eq!(parse_range("1+0xffffffffffffffff"), Ok(1..u64::MAX));
+    assert_eq!(
+      parse_range("0x20..0x10"),
+      Err("0x20..0x10 ends before it starts".into())
+    );
+    assert!(parse_range("0x10").is_err());
+    assert!(parse_range("..0x10").is_err());
+    assert!(parse_range("0x10..").is_err());
+  }
+
+  #[test]
+  #[allow(clippy::single_range_in_vec_init)]
+  fn split_ranges() {
+    assert_eq!(split(0..10, &[]), [0..10]);
+    assert_eq!(split(0..10, &[2..4]), [0..2, 2..4, 4..10]);
+    // Overlapping and out of range cuts.
+    assert_eq!(split(0..10, &[5..20, 2..6]), [0..2, 2..5, 5..6, 6..10]);
+    assert_eq!(split(4..6, &[0..4, 6..9, 0..10]), [4..6]);
+  }
+
+  #[test]
+  fn address_map() {
+    let src = "
+      # Flash, then RAM.
+      0x0..0x1000     0x08000000
+      0x1000+0x400    0x20000000
+
+      0x2000..0x2000  0x0
+    ";
+    let map = AddressMap::parse(src).unwrap();
+    assert_eq!(map.entries.len(), 3);
+    assert_eq!(map.get(0), Some(0x0800_0000));
+    assert_eq!(map.get(0xfff), Some(0x0800_0fff));
+    assert_eq!(map.get(0x1000), Some(0x2000_0000));
+    assert_eq!(map.get(0x13ff), Some(0x2000_03ff));
+    assert_eq!(map.get(0x1400), None);
+    assert_eq!(map.get(0x2000), None);
+
+    let err = |src| AddressMap::parse(src).unwrap_err();
+    assert_eq!(err("0x0..0x10"), "line 1: expected `range address`");
+    assert_eq!(
+      err("\n0x10..0x0 0x100"),
+      "line 2: 0x10..0x0 ends before it starts"
+    );
+    assert_eq!(err("0..4 zz"), "line 1: zz: invalid digit found in string");
+    assert_eq!(
+      err("0x0..0x10 0xfffffffffffffff8"),
+      "line 1: 0x0..0x10 at 0xfffffffffffffff8 runs past the top of memory"
+    );
+  }
+
+  /// Address maps can cover the very top of memory, without overflowing.
+  #[test]
+  fn address_map_top() {
+    let map = AddressMap::parse("0xffffffffffffffff..0xffffffffffffffff 0");
+    assert!(map.is_ok());
+    let map = AddressMap::parse("0xffffffffffffffff+1 0x10").unwrap();
+    assert_eq!(map.get(u64::MAX), None);
+    let map = AddressMap::parse("0..0x10 0xfffffffffffffff0").unwrap();
+    assert_eq!(map.get(0xf), Some(u64::MAX));
+  }
+}
@@ -13,25 +13,36 @@
 // limitations under the License.
 
 //! ohx (`0x`) -- like xxd, but colorful!
-//! 
+//!
 //! We try to match as much of the xxd CLI as is reasonable, but we don't
 //! promise exact compatibility.
 
-use std::env;
+use std::fs;
 use std::fs::File;
 use std::io;
-use std::io::Seek;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
+use std::thread;
 
 use argh::FromArgs;
 
 use palette::Srgb;
 
+mod annotate;
 mod calc;
+mod checksum;
 mod color;
+mod compress;
+mod input;
+mod records;
 mod render;
+mod sections;
+mod theme;
 
 /// Parses an RGB hex value, or one of the named CSS colors in `palette`.
 fn parse_rgb(s: &str) -> Result<Srgb<u8>, String> {
@@ -41,10 +52,62 @@ fn parse_rgb(s: &str) -> Result<Srgb<u8>, String> {
   s.parse::<Srgb<u8>>().map_err(|e| e.to_string())
 }
 
-struct Gradient(Vec<Srgb<u8>>);
-impl argh::FromArgValue for Gradient {
-  fn from_arg_value(s: &str) -> Result<Gradient, String> {
-    let well_known = match s.to_uppercase().as_str() {
+/// Parses a decimal or `0x`-prefixed hex integer.
+fn parse_int(s: &str) -> Result<u64, String> {
+  let s = s.trim();
+  match s.strip_prefix("0x") {
+    Some(hex) => u64::from_str_radix(hex, 16),
+    None => s.parse(),
+  }
+  .map_err(|e| format!("{s}: {e}"))
+}
+
+struct Colors {
+  map: render::ColorMap,
+  /// Whether this is a named gradient sampled at many points, whose
+  /// neighboring colors are meant to be close together.
+  sampled: bool,
+}
+impl argh::FromArgValue for Colors {
+  fn from_arg_value(s: &str) -> Result<Colors, String> {
+    // Named schemes can be reversed with an `_r` suffix, like in matplotlib.
+    let name = s.to_uppercase();
+    let (name, reversed) = match name.strip_suffix("_R") {
+      Some(name) => (name, true),
+      None => (name.as_str(), false),
+    };
+
+    let categorical: Option<&[colorous::Color]> = match name {
+      "ACCENT" => Some(&colorous::ACCENT),
+      "CATEGORY10" => Some(&colorous::CATEGORY10),
+      "DARK2" => Some(&colorous::DARK2),
+      "PAIRED" => Some(&colorous::PAIRED),
+      "PASTEL1" => Some(&colorous::PASTEL1),
+      "PASTEL2" => Some(&colorous::PASTEL2),
+      "SET1" => Some(&colorous::SET1),
+      "SET2" => Some(&colorous::SET2),
+      "SET3" => Some(&colorous::SET3),
+      "TABLEAU10" => Some(&colorous::TABLEAU10),
+      "OKABE-ITO" => Some(&color::OKABE_ITO),
+      "TOL-BRIGHT" => Some(&color::TOL_BRIGHT),
+      "TOL-VIBRANT" => Some(&color::TOL_VIBRANT),
+      _ => None,
+    };
+    if let Some(colors) = categorical {
+      let mut colors = colors
+        .iter()
+        .map(|&colorous::Color { r, g, b }| Srgb::new(r, g, b))
+        .collect::<Vec<_>>();
+      if reversed {
+        colors.reverse();
+      }
+      return Ok(Self {
+        map: render::ColorMap::Classes(colors),
+        sampled: false,
+      });
+    }
+
+    let well_known = match name {
       "BLUES" => Some(colorous::BLUES),
       "BLUE_GREEN" => Some(colorous::BLUE_GREEN),
       "BLUE_PURPLE" => Some(colorous::BLUE_PURPLE),
@@ -88,25 +151,233 @@ impl argh::FromArgValue for Gradient {
 
     // Sample sixteen points.
     if let Some(gradient) = well_known {
-      return Ok(Self(
-        (0..16)
-          .map(|i| {
-            let colorous::Color { r, g, b } = gradient.eval_rational(i, 16);
-            Srgb::new(r, g, b)
-          })
-          .collect(),
-      ));
+      let stops = color::Stop::spread((0..16).map(|i| {
+        let i = if reversed { 15 - i } else { i };
+        let colorous::Color { r, g, b } = gradient.eval_rational(i, 16);
+        Srgb::new(r, g, b)
+      }));
+      return Ok(Self {
+        map: render::ColorMap::Gradient(stops),
+        sampled: true,
+      });
+    }
+
+    // `value=color` pairs make a table; they can't be mixed with bare colors.
+    if s.contains('=') {
+      let mut table = Vec::new();
+      for entry in s.split(',') {
+        let (value, color) = entry
+          .split_once('=')
+          .ok_or_else(|| format!("expected `value=color`, got {entry}"))?;
+        table.push((parse_int(value)?, parse_rgb(color)?));
+      }
+      return Ok(Self {
+        map: render::ColorMap::Table(table),
+        sampled: false,
+      });
+    }
+
+    // Colors may be pinned to a position with `color@pos`; the rest are
+    // spread evenly between their neighbors.
+    let mut stops = Vec::new();
+    let mut pinned = Vec::new();
+    for entry in s.split(',') {
+      let (color, pos) = match entry.split_once('@') {
+        Some((color, pos)) => {
+          let pos = pos
+            .parse::<f32>()
+            .map_err(|e| format!("bad gradient position {pos}: {e}"))?;
+          if !(0.0..=1.0).contains(&pos) {
+            return Err(format!("gradient position {pos} is not in 0..=1"));
+          }
+          pinned.push(stops.len());
+          (color, pos)
+        }
+        None => (entry, f32::NAN),
+      };
+      stops.push(color::Stop {
+        pos,
+        color: parse_rgb(color)?,
+      });
+    }
+
+    let last = stops.len() - 1;
+    if stops[0].pos.is_nan() {
+      stops[0].pos = 0.0;
+      pinned.insert(0, 0);
+    }
+    if stops[last].pos.is_nan() {
+      stops[last].pos = 1.0;
+      pinned.push(last);
+    }
+    for pair in pinned.windows(2) {
+      let (i, j) = (pair[0], pair[1]);
+      let (from, to) = (stops[i].pos, stops[j].pos);
+      if to < from {
+        return Err("gradient positions must be increasing".into());
+      }
+      let steps = (j - i) as f32;
+      for (k, stop) in stops[i + 1..j].iter_mut().enumerate() {
+        stop.pos = from + (to - from) * (k + 1) as f32 / steps;
+      }
+    }
+    Ok(Self {
+      map: render::ColorMap::Gradient(stops),
+      sampled: false,
+    })
+  }
+}
+
+impl Colors {
+  /// Unwraps the parsed colors as seen with `vision`, turning a gradient into
+  /// classes if `categorical` is set. When simulating a deficiency, warns
+  /// about colors that are hard to tell apart.
+  fn into_map(
+    self,
+    categorical: bool,
+    vision: Option<color::Deficiency>,
+  ) -> render::ColorMap {
+    let mut map = match self.map {
+      render::ColorMap::Gradient(stops) if categorical => {
+        render::ColorMap::Classes(stops.iter().map(|s| s.color).collect())
+      }
+      map => map,
+    };
+    if let Some(d) = vision {
+      if !self.sampled {
+        let colors = map.colors().into_iter();
+        let named = colors.map(|c| (format!("#{c:x}"), c)).collect::<Vec<_>>();
+        warn_similar("-z", &named, false, d);
+      }
+      map.recolor(|c| d.simulate(c));
+    }
+    map
+  }
+}
+
+/// Warns about colors that are hard to tell apart when seen with `vision`.
+/// Only neighbors are compared, unless `all_pairs` is set.
+fn warn_similar(
+  what: &str,
+  colors: &[(String, Srgb<u8>)],
+  all_pairs: bool,
+  vision: color::Deficiency,
+) {
+  let vision_name = format!("{vision:?}").to_lowercase();
+  for (i, (a, ca)) in colors.iter().enumerate() {
+    let mut others = &colors[i + 1..];
+    if !all_pairs {
+      others = &others[..others.len().min(1)];
     }
+    for (b, cb) in others {
+      let diff = color::delta_e(vision.simulate(*ca), vision.simulate(*cb));
+      if diff < color::MIN_DELTA_E {
+        eprintln!(
+          "eks: warning: {what} colors {a} and {b} are hard to tell apart \
+           with {vision_name} (ΔE = {diff:.1})"
+        );
+      }
+    }
+  }
+}
+
+/// Parses a position in the input; negative ones count back from the end.
+fn parse_pos(s: &str) -> Result<input::Pos, String> {
+  match s.trim().strip_prefix('-') {
+    Some(n) => Ok(input::Pos::End(parse_int(n)?)),
+    None => Ok(input::Pos::Start(parse_int(s)?)),
+  }
+}
+
+enum Cols {
+  Bytes(u32),
+  Auto,
+}
+impl argh::FromArgValue for Cols {
+  fn from_arg_value(s: &str) -> Result<Cols, String> {
+    match s {
+      "auto" => Ok(Cols::Auto),
+      _ => s.parse().map(Cols::Bytes).map_err(|e| format!("{s}: {e}")),
+    }
+  }
+}
+
+struct Bits(Vec<u32>);
+impl argh::FromArgValue for Bits {
+  fn from_arg_value(s: &str) -> Result<Bits, String> {
+    let widths = s
+      .split(',')
+      .map(|w| match w.trim().parse() {
+        Ok(w @ 1..=64) => Ok(w),
+        Ok(_) => Err(format!("{w}: fields must be 1 to 64 bits wide")),
+        Err(e) => Err(format!("{w}: {e}")),
+      })
+      .collect::<Result<_, _>>()?;
+    Ok(Bits(widths))
+  }
+}
+
+struct Checksums(Vec<checksum::Algorithm>);
+impl argh::FromArgValue for Checksums {
+  fn from_arg_value(s: &str) -> Result<Checksums, String> {
+    let algorithms = s
+      .split(',')
+      .map(|a| checksum::Algorithm::from_arg_value(a.trim()))
+      .collect::<Result<_, _>>()?;
+    Ok(Checksums(algorithms))
+  }
+}
 
-    let mut gradient = Vec::new();
-    for color in s.split(",") {
-      gradient.push(parse_rgb(color)?);
+struct Ranges(Vec<input::Span>);
+impl argh::FromArgValue for Ranges {
+  fn from_arg_value(s: &str) -> Result<Ranges, String> {
+    let mut spans = Vec::new();
+    for entry in s.split(',') {
+      let span = if let Some((start, end)) = entry.split_once("..") {
+        input::Span {
+          start: match start.trim() {
+            "" => input::Pos::Start(0),
+            start => parse_pos(start)?,
+          },
+          end: match end.trim() {
+            "" => input::SpanEnd::Open,
+            end => input::SpanEnd::At(parse_pos(end)?),
+          },
+        }
+      } else if let Some((start, len)) = entry.split_once('+') {
+        input::Span {
+          start: parse_pos(start)?,
+          end: input::SpanEnd::Len(parse_int(len)?),
+        }
+      } else {
+        return Err(format!(
+          "expected `start..end` or `start+len`, got {entry}"
+        ));
+      };
+      spans.push(span);
     }
-    Ok(Self(gradient))
+    Ok(Ranges(spans))
   }
 }
 
 struct MaybeAscii(Option<render::AsciiOpts>);
+
+impl MaybeAscii {
+  /// Unwraps the parsed colors as seen with `vision`. When simulating a
+  /// deficiency, warns about colors that are hard to tell apart.
+  fn into_opts(
+    self,
+    vision: Option<color::Deficiency>,
+  ) -> Option<render::AsciiOpts> {
+    let mut ascii = self.0;
+    if let (Some(ascii), Some(d)) = (&mut ascii, vision) {
+      let named = ascii.colors().map(|(name, c)| (name.to_string(), c));
+      warn_similar("-y", &named, true, d);
+      ascii.recolor(|c| d.simulate(c));
+    }
+    ascii
+  }
+}
 impl argh::FromArgValue for MaybeAscii {
   fn from_arg_value(mut s: &str) -> Result<MaybeAscii, String> {
     match s.to_lowercase().as_str() {
@@ -117,6 +388,13 @@ impl argh::FromArgValue for MaybeAscii {
       "monokai" => {
         s = "#AE81FF,#66D9EF,#A6E22E,#F92672,slategray";
       }
+      // These two stay readable with any common color vision deficiency.
+      "okabe-ito" => {
+        s = "#56B4E9,#E69F00,#F0E442,#CC79A7,#0072B2";
+      }
+      "tol-bright" => {
+        s = "#66CCEE,#CCBB44,#EE6677,#AA3377,#BBBBBB";
+      }
       _ => {}
     }
 
@@ -138,14 +416,15 @@ impl argh::FromArgValue for MaybeAscii {
 /// convert binary input to color-coded hex (or other power-of-2 bases)
 #[derive(FromArgs)]
 struct Eks {
-  /// the base to print bytes in: 2, 4, 8, 16, 32, or 64. for bases that not
-  /// powers of powers of two, a "byte" is lcm(8, log2(base)) bits wide
+  /// the base to print bytes in: 2, 4, 8, 16, 32, or 64. in bases 8 and 64,
+  /// -g and -c must be multiples of 3 bytes, and in base 32 of 5 bytes, so
+  /// that words are a whole number of glyphs
   #[argh(option, short = 'b', default = "16")]
   base: u32,
 
-  /// number of "words" in a line
+  /// number of bytes in a line, or "auto" to fill the terminal
   #[argh(option, short = 'c')]
-  cols: Option<u32>,
+  cols: Option<Cols>,
 
   /// print words as little-endian rather than big-endian. bytes are always
   /// little-endian.
@@ -156,17 +435,47 @@ struct Eks {
   #[argh(option, short = 'g')]
   groups: Option<u32>,
 
+  /// evaluate -x and --style on lanes of this many bytes of each word, up
+  /// to 8, read in the word's byte order. defaults to a byte, or to the
+  /// fewest bytes that are a whole number of glyphs in bases 8, 32, and 64
+  #[argh(option)]
+  lanes: Option<u32>,
+
+  /// in base 2, split each word into bit fields of these widths, such as
+  /// "3,5", repeated as needed and each colored by its own value. words are
+  /// as wide as the fields unless -g says otherwise
+  #[argh(option)]
+  bits: Option<Bits>,
+
+  /// in base 2, how many bits into each word the fields of --bits start, up
+  /// to 63
+  #[argh(option, default = "0")]
+  bit_offset: u32,
+
+  /// in base 2, which bit of each byte to draw first: "msb" (the default)
+  /// or "lsb"
+  #[argh(option, default = "Default::default()")]
+  bit_order: render::BitOrder,
+
   /// stop after a number of bytes
-  #[argh(option, short = 'l', default = "u64::MAX")]
-  limit: u64,
+  #[argh(option, short = 'l', from_str_fn(parse_int))]
+  limit: Option<u64>,
 
   /// add a fixed offset to the displayed file positions
-  #[argh(option, short = 'o')]
+  #[argh(option, short = 'o', from_str_fn(parse_int))]
   offset: Option<u64>,
 
-  /// seek ahead of the input before decoding
-  #[argh(option, short = 's', default = "0")]
-  seek: i64,
+  /// seek ahead of the input before decoding; negative values count back
+  /// from the end. on pipes, this reads and discards the skipped bytes
+  #[argh(option, short = 's', from_str_fn(parse_pos))]
+  seek: Option<input::Pos>,
+
+  /// comma-separated byte ranges to dump instead of the whole input, each
+  /// either `start..end` (either side may be left off) or `start+len`.
+  /// negative positions count back from the end, so `-512..` is the last
+  /// 512 bytes
+  #[argh(option)]
+  range: Option<Ranges>,
 
   /// use uppercase letters for printing
   #[argh(switch, short = 'u')]
@@ -177,30 +486,92 @@ struct Eks {
   version: bool,
 
   /// formula for picking which of the 256 colors to give each byte, in RPN.
-  /// valid operands are x (for the byte itself), literal decimal or hex bytes,
-  /// and the operators +, -, *, /, %, &, |, ^, >>, <<, >>> (arithmetic shift),
-  /// ! (one's complement) and ~ (two's complement). the stack starts with x at
-  /// at the top followed by infinite zeros.
-  #[argh(option, short = 'x', default = "Default::default()")]
-  calc: calc::Calc,
+  /// valid operands are x (for the byte itself), bits (the width of x),
+  /// literal decimal or hex bytes, the operators +, -, *, /, %, &, |, ^, >>,
+  /// <<, >>> (arithmetic shift), ! (one's complement), ~ (two's complement),
+  /// the comparisons =, !=, <, >, <=, >=, and the stack words dup, drop, swap,
+  /// over and rot. the stack starts with x at the top followed by infinite
+  /// zeros. new words can be defined with `: name ... ;`, and the words
+  /// ascii_class, utf8_class, is_zero, sign, and high_nibble are built in.
+  /// numbers are masked to the width of x, so ascii_class and utf8_class need
+  /// whole bytes, and don't work with --color-single-glyphs
+  #[argh(option, short = 'x', default = "String::new()")]
+  calc: String,
+
+  /// formula for picking text attributes for each byte, in the same language
+  /// as -x. the result is a set of flags: 1 (bold), 2 (dim), 4 (underline),
+  /// and 8 (highlight, in the theme's highlight color), also available as the
+  /// words bold, dim, underline, and highlight. the word pos gives the
+  /// position of a byte within its word, so
+  /// `x is_zero dim * pos 0 = underline * |` dims zeros and underlines the
+  /// start of each word
+  #[argh(option)]
+  style: Option<String>,
+
+  /// color the background of each byte rather than its glyphs, with black or
+  /// white text on top
+  #[argh(switch)]
+  background: bool,
+
+  /// print a legend after the dump, showing the formula result at each of the
+  /// gradient's colors
+  #[argh(switch)]
+  legend: bool,
+
+  /// instead of dumping anything, show how a gradient (in the same syntax as
+  /// -z) looks in truecolor, 256 colors, and 16 colors
+  #[argh(option)]
+  preview_gradient: Option<Colors>,
+
+  /// show colors as they'd look with a color vision deficiency: "protanopia",
+  /// "deuteranopia", or "tritanopia". colors that become hard to tell apart
+  /// are reported on stderr
+  #[argh(option)]
+  simulate: Option<color::Deficiency>,
+
+  /// file of formula definitions to load before -x; may be repeated
+  #[argh(option)]
+  calc_file: Vec<PathBuf>,
+
+  /// how to evaluate formulas: "auto", "tree", or "interpret". only useful
+  /// for benchmarking
+  #[argh(option, default = "Default::default()", hidden_help)]
+  calc_strategy: calc::Strategy,
 
   /// colors for the ASCII render of each line of bytes. must be five
   /// comma-separated colors for uppercase, lowercase, digits, punctuation, and
   /// unprintable characters; disable with "none"
-  #[argh(
-    option,
-    short = 'y',
-    default = "argh::FromArgValue::from_arg_value(\"mariana\").unwrap()"
-  )]
-  ascii: MaybeAscii,
-
-  /// comma-separated colors for the byte-coloring gradient
-  #[argh(
-    option,
-    short = 'z',
-    default = "argh::FromArgValue::from_arg_value(\"red,orangered,orange,gold,yellow,lightyellow\").unwrap()"
-  )]
-  gradient: Gradient,
+  #[argh(option, short = 'y')]
+  ascii: Option<MaybeAscii>,
+
+  /// comma-separated colors for the byte-coloring gradient, optionally placed
+  /// with `color@pos` where pos is between 0 and 1, or a list of
+  /// `value=color` pairs to color exact formula results. named gradients such
+  /// as "viridis" can be reversed with an `_r` suffix. categorical schemes
+  /// such as "category10" or "set1" color each formula result modulo the
+  /// number of colors, without blending
+  #[argh(option, short = 'z')]
+  gradient: Option<Colors>,
+
+  /// color space to blend gradient colors in: "hsv", "rgb" (linear), "lab",
+  /// "lch", or "oklab"
+  #[argh(option)]
+  gradient_space: Option<color::Space>,
+
+  /// color theme, which sets the defaults for -y, -z, and --gradient-space,
+  /// and colors row labels, separators, and highlights: either the name of a
+  /// built-in theme, a name from ~/.config/0x/themes, or a path to a
+  /// .theme file
+  #[argh(option)]
+  theme: Option<theme::Theme>,
+
+  /// instead of dumping anything, show a sample dump in every theme
+  #[argh(switch)]
+  list_themes: bool,
+
+  /// use the colors given to -z as discrete classes rather than a gradient
+  #[argh(switch)]
+  categorical: bool,
 
   /// whether to color single glyphs rather than the bytes they're part of
   #[argh(switch)]
@@ -211,16 +582,223 @@ struct Eks {
   #[argh(option, default = "render::RowLabelStyle::Byte")]
   row_label_style: render::RowLabelStyle,
 
+  /// the base to write row labels in: "hex" (the default), "dec", "oct", or
+  /// "dump" for the same base as the bytes
+  #[argh(option, default = "render::RowLabelBase::Hex")]
+  row_label_base: render::RowLabelBase,
+
+  /// label rows by their distance from this offset, such as the start of a
+  /// structure, rather than from the start of the input
+  #[argh(option, from_str_fn(parse_int))]
+  relative_to: Option<u64>,
+
+  /// a file of `start..end address` lines, giving the addresses that parts
+  /// of the input are mapped to; rows in those parts are labeled with them
+  #[argh(option)]
+  address_map: Option<input::AddressMap>,
+
+  /// a file of `start..end color "note"` lines: each range is highlighted in
+  /// its color, and its note is written beside the line it starts on
+  #[argh(option)]
+  annotate: Option<annotate::Annotations>,
+
+  /// number the notes from --annotate, and list them after the dump rather
+  /// than writing them out beside it
+  #[argh(switch)]
+  footnotes: bool,
+
+  /// comma-separated checksums to write after the dump, of each range and
+  /// of all of them: crc8, crc8-maxim, crc16, crc16-ccitt, crc16-xmodem,
+  /// crc16-modbus, crc32, crc32c, crc32-mpeg2, crc32-bzip2, adler32, fnv32,
+  /// fnv64, sha256, sha1, or md5
+  #[argh(option)]
+  checksum: Option<Checksums>,
+
+  /// also write the checksums from --checksum of each line, at the end of
+  /// the line
+  #[argh(switch)]
+  line_checksums: bool,
+
+  /// when to use color: "auto" (the default) only colors terminals, "always"
+  /// and "never" do what they say, and "16", "256", and "truecolor" always
+  /// color with that many colors. auto respects NO_COLOR and CLICOLOR_FORCE
+  #[argh(option, default = "Default::default()")]
+  color: color::When,
+
   /// force enable or disable truecolor, instead of detecting it
   #[argh(option)]
   force_truecolor: Option<bool>,
 
-  /// input path to read from, and output path to write to;
-  /// - (the default) means stdin/stdout
+  /// number of threads to render with; defaults to one per CPU for regular
+  /// files, and one for anything else
+  #[argh(option, short = 'j')]
+  jobs: Option<usize>,
+
+  /// keep waiting for more input at the end, like `tail -f`. combine with
+  /// -s to start from the last few bytes
+  #[argh(switch, short = 'f')]
+  follow: bool,
+
+  /// what to write the dump as: "text" (the default), or "json" or "jsonl"
+  /// for an object per line giving its bytes, glyphs, formula results, and
+  /// colors, for other tools to draw
+  #[argh(option, default = "Default::default()")]
+  format: render::Format,
+
+  /// output path to write to; - (the default) means stdout
+  #[argh(option, short = 'O')]
+  output: Option<PathBuf>,
+
+  /// dump gzip, zstd, xz, and bzip2 inputs as they are, rather than
+  /// decompressing them
+  #[argh(switch)]
+  raw: bool,
+
+  /// label rows of compressed inputs with roughly where they are in the
+  /// compressed data, rather than in the decompressed data
+  #[argh(switch)]
+  compressed_offsets: bool,
+
+  /// read the inputs as Intel HEX ("ihex") or S-record ("srec") files,
+  /// checking their checksums, and dump the memory images they describe
+  #[argh(option)]
+  from: Option<records::Format>,
+
+  /// write the dumped bytes as "ihex", "s19", "s28", or "s37" records at
+  /// the addresses they're labeled with, rather than as a hexdump
+  #[argh(option)]
+  to: Option<records::Format>,
+
+  /// color ELF, PE, and Mach-O files by section rather than with -x, and
+  /// mark where each section starts
+  #[argh(switch)]
+  sections: bool,
+
+  /// dump only the named section of an ELF, PE, or Mach-O file, labeled
+  /// with its virtual addresses; may be given more than once
+  #[argh(option)]
+  section: Vec<String>,
+
+  /// dump all inputs as if they were one file, rather than one at a time
+  /// under a header each
+  #[argh(switch)]
+  concat: bool,
+
+  /// input paths to read from; - (the default) means stdin
   #[argh(positional)]
   files: Vec<PathBuf>,
 }
 
+/// Parses the colors in `theme`: its gradient, gradient space, ASCII colors,
+/// and accents.
+fn theme_colors(
+  theme: &theme::Theme,
+) -> Result<(Colors, color::Space, MaybeAscii, render::Accents), String> {
+  use argh::FromArgValue;
+  let key = |key: &'static str| move |e: String| format!("{key}: {e}");
+  let rgb = |value: &Option<String>, name| {
+    value
+      .as_deref()
+      .map(parse_rgb)
+      .transpose()
+      .map_err(key(name))
+  };
+  Ok((
+    Colors::from_arg_value(&theme.gradient).map_err(key("gradient"))?,
+    color::Space::from_arg_value(&theme.gradient_space)
+      .map_err(key("gradient-space"))?,
+    MaybeAscii::from_arg_value(&theme.ascii).map_err(key("ascii"))?,
+    render::Accents {
+      row_label: rgb(&theme.row_label, "row-label")?,
+      separator: rgb(&theme.separator, "separator")?,
+      highlight: parse_rgb(&theme.highlight).map_err(key("highlight"))?,
+      header: parse_rgb(&theme.header).map_err(key("header"))?,
+    },
+  ))
+}
+
+/// Bytes dumped by --list-themes.
+const THEME_SAMPLE: &[u8] = b"0x: like xxd, but colorful!\n\x00\x7f\x80\xff";
+
+/// Prints a sample dump in each theme.
+fn list_themes(
+  depth: color::Depth,
+  vision: Option<color::Deficiency>,
+) -> io::Result<()> {
+  let mut stdout = io::stdout();
+  for theme in theme::Theme::all() {
+    // Broken user themes shouldn't hide the rest.
+    let theme = match theme {
+      Ok(theme) => theme,
+      Err(e) => {
+        eprintln!("eks: warning: {e}");
+        continue;
+      }
+    };
+    let (gradient, gradient_space, ascii, mut accents) =
+      match theme_colors(&theme) {
+        Ok(colors) => colors,
+        Err(e) => {
+          eprintln!("eks: warning: theme {}: {e}", theme.name);
+          continue;
+        }
+      };
+    if let Some(d) = vision {
+      accents.recolor(|c| d.simulate(c));
+    }
+    writeln!(stdout, "{}:", theme.name)?;
+    render::RenderOpts {
+      log2_base: 4,
+      bytes_per_word: 4,
+      words_per_line: 4,
+      fit_width: false,
+      display_offset_start: 0,
+      regions: vec![render::Region::new(0..u64::MAX)],
+      header: None,
+      format: render::Format::Text,
+      little_endian: false,
+      bit_fields: None,
+      bit_offset: 0,
+      bit_order: render::BitOrder::Msb,
+      lanes: None,
+
+      colors: gradient.into_map(false, vision),
+      gradient_space,
+      depth,
+      background: false,
+      legend: false,
+      ascii: ascii.into_opts(vision),
+      accents,
+      color_single_glyphs: false,
+      uppercase: false,
+
+      row_label_style: render::RowLabelStyle::Byte,
+      row_label_base: render::RowLabelBase::Hex,
+      relative_to: None,
+      calc: calc::Calc::parse("").expect("bad formula"),
+      style: Some(
+        calc::Calc::parse("x 0x80 >= highlight *").expect("bad formula"),
+      ),
+      calc_strategy: Default::default(),
+      annotations: Vec::new(),
+      footnotes: false,
+      checksums: Vec::new(),
+      line_checksums: false,
+      jobs: 1,
+      sections: None,
+      compressed_offsets: false,
+      follow: false,
+      redraw: false,
+
+      r: &mut input::Input::stream(THEME_SAMPLE),
+      w: &mut stdout,
+    }
+    .render()?;
+    writeln!(stdout)?;
+  }
+  Ok(())
+}
+
 fn real_main() -> io::Result<()> {
   let eks: Eks = argh::from_env();
 
@@ -234,38 +812,140 @@ fn real_main() -> io::Result<()> {
     return Ok(());
   }
 
-  let stdio = Path::new("-");
-  let (mut input, mut output) = match &eks.files[..] {
-    [] => (None, None),
-    [inp] => ((inp != stdio).then(|| File::open(inp)).transpose()?, None),
-    [inp, out] => (
-      (inp != stdio).then(|| File::open(inp)).transpose()?,
-      (out != stdio).then(|| File::create(out)).transpose()?,
-    ),
-    _files => {
-      eprintln!("eks: only allow up to two file arguments");
-      exit(1);
+  // --force-truecolor predates --color, and implies that color is wanted.
+  let when = match (eks.color, eks.force_truecolor) {
+    (color::When::Never, _) | (_, None) => eks.color,
+    (_, Some(true)) => color::When::Force(color::Depth::True),
+    (_, Some(false)) => color::When::Force(color::Depth::Xterm256),
+  };
+
+  if eks.list_themes {
+    let depth = color::Depth::detect(when, io::stdout().is_terminal());
+    return list_themes(depth, eks.simulate);
+  }
+
+  let theme = match eks.theme {
+    Some(theme) => theme,
+    None => theme::Theme::builtin("default").expect("no default theme"),
+  };
+  let (gradient, gradient_space, ascii, mut accents) =
+    match theme_colors(&theme) {
+      Ok(colors) => colors,
+      Err(e) => {
+        eprintln!("eks: theme {}: {e}", theme.name);
+        exit(1);
+      }
+    };
+  let gradient = match eks.gradient {
+    Some(gradient) => gradient,
+    // Sections are easier to tell apart with unrelated colors.
+    None if eks.sections => {
+      argh::FromArgValue::from_arg_value("tableau10").expect("bad scheme")
     }
+    None => gradient,
   };
+  let gradient_space = eks.gradient_space.unwrap_or(gradient_space);
+  let ascii = eks.ascii.unwrap_or(ascii);
+  if let Some(d) = eks.simulate {
+    accents.recolor(|c| d.simulate(c));
+  }
+
+  if let Some(colors) = eks.preview_gradient {
+    let map = colors.into_map(eks.categorical, eks.simulate);
+    return render::preview(&map, gradient_space, &mut io::stdout());
+  }
+
+  let stdio = Path::new("-");
+  let mut output = match eks.output.as_deref() {
+    Some(out) if out != stdio => Some(File::create(out).map_err(|e| {
+      io::Error::new(e.kind(), format!("{}: {e}", out.display()))
+    })?),
+    _ => None,
+  };
+
+  let paths = match &eks.files[..] {
+    [] => vec![stdio.to_path_buf()],
+    files => files.to_vec(),
+  };
+  let mut inputs = Vec::new();
+  for (i, path) in paths.into_iter().enumerate() {
+    let input = match path == stdio {
+      true => Ok(input::Input::stream(io::stdin())),
+      false => File::open(&path).and_then(input::Input::file),
+    };
+    // `0x in out` used to write to `out`, as xxd does.
+    let input = input.map_err(|e| match e.kind() {
+      io::ErrorKind::NotFound if i > 0 => io::Error::new(
+        e.kind(),
+        format!(
+          "{e}; to write the dump to a file, use -O {}",
+          path.display()
+        ),
+      ),
+      _ => e,
+    });
+    let input = input
+      .and_then(|input| match eks.raw {
+        true => Ok(input),
+        false => input.decompress(),
+      })
+      .and_then(|input| match eks.from {
+        Some(format) => load_image(input, format),
+        None => Ok((input, None)),
+      })
+      .map_err(|e| {
+        io::Error::new(e.kind(), format!("{}: {e}", input_name(&path)))
+      })?;
+    inputs.push((path, input.0, input.1));
+  }
+  if eks.concat {
+    let parts = inputs.drain(..).map(|(_, input, _)| input).collect();
+    inputs.push((stdio.to_path_buf(), input::Input::concat(parts), None));
+  }
 
-  let start_offset = if let Some(file) = &mut input {
-    if eks.seek > 0 {
-      file.seek(io::SeekFrom::Start(eks.seek as u64))?;
-    } else if eks.seek < 0 {
-      file.seek(io::SeekFrom::End(eks.seek))?;
+  if eks.from.is_some()
+    && (eks.concat
+      || eks.address_map.is_some()
+      || eks.sections
+      || !eks.section.is_empty())
+  {
+    eprintln!(
+      "eks: --from cannot be used with --concat, --address-map, --sections, \
+       or --section"
+    );
+    exit(1);
+  }
+
+  if !eks.section.is_empty()
+    && (eks.range.is_some() || eks.seek.is_some() || eks.limit.is_some())
+  {
+    eprintln!("eks: --section cannot be used with -s, -l, or --range");
+    exit(1);
+  }
+
+  // -s and -l are shorthand for a single range.
+  let spans = match eks.range {
+    Some(_) if eks.seek.is_some() || eks.limit.is_some() => {
+      eprintln!("eks: --range cannot be used with -s or -l");
+      exit(1);
     }
-    file.stream_position()?
-  } else {
-    0
+    Some(ranges) => ranges.0,
+    None => vec![input::Span {
+      start: eks.seek.unwrap_or(input::Pos::Start(0)),
+      end: eks.limit.map_or(input::SpanEnd::Open, input::SpanEnd::Len),
+    }],
   };
 
-  let (log2_base, bytes_per_word) = match eks.base {
-    2 => (1, 1),
-    4 => (2, 2),
-    8 => (3, 1),
-    16 => (4, 4),
-    32 => (5, 1),
-    64 => (6, 2),
+  // Bases that don't divide a byte evenly need words and lines that are a
+  // whole number of glyphs wide, which is a multiple of `glyph_bytes`.
+  let (log2_base, glyph_bytes, bytes_per_word, bytes_per_line) = match eks.base
+  {
+    2 => (1, 1, 1, 16),
+    4 => (2, 1, 2, 16),
+    8 => (3, 3, 3, 48),
+    16 => (4, 1, 4, 16),
+    32 => (5, 5, 5, 80),
+    64 => (6, 3, 6, 48),
     _ => {
       eprintln!("eks: base must be 2, 4, or 16");
       exit(1);
@@ -277,45 +957,422 @@ fn real_main() -> io::Result<()> {
     exit(1);
   }
 
-  let bytes_per_word = eks.groups.unwrap_or(bytes_per_word);
-  let words_per_line = eks.cols.unwrap_or(16) / bytes_per_word;
+  let bit_fields = eks.bits.map(|b| b.0);
+  if eks.base != 2
+    && (bit_fields.is_some()
+      || eks.bit_offset != 0
+      || eks.bit_order != render::BitOrder::Msb)
+  {
+    eprintln!("eks: --bits, --bit-offset, and --bit-order need -b 2");
+    exit(1);
+  }
+  // The skipped bits are drawn as a field of their own, which has to fit in
+  // a u64 like any other.
+  if eks.bit_offset >= 64 {
+    eprintln!("eks: --bit-offset must be less than 64");
+    exit(1);
+  }
+
+  // Words are just wide enough for one run of the bit fields.
+  let bytes_per_word = match &bit_fields {
+    Some(widths) if eks.groups.is_none() => {
+      let bits = eks.bit_offset + widths.iter().sum::<u32>();
+      bits.div_ceil(8)
+    }
+    _ => eks.groups.unwrap_or(bytes_per_word),
+  };
+  if bytes_per_word == 0 {
+    eprintln!("eks: -g must be at least 1");
+    exit(1);
+  }
+  // -g and -c used to count 24- and 40-bit chunks in these bases; catch
+  // old invocations rather than silently drawing something else.
+  let partial = eks.groups.is_some_and(|g| g % glyph_bytes != 0)
+    || matches!(eks.cols, Some(Cols::Bytes(c)) if c % glyph_bytes != 0);
+  if partial {
+    eprintln!(
+      "eks: in base {}, -g and -c count bytes and must be multiples of {}, \
+       so that words are a whole number of glyphs",
+      eks.base, glyph_bytes
+    );
+    exit(1);
+  }
+  if eks.lanes.is_some_and(|n| !(1..=8).contains(&n)) {
+    eprintln!("eks: --lanes must be from 1 to 8 bytes");
+    exit(1);
+  }
+  let (words_per_line, fit_width) = match eks.cols {
+    None => (bytes_per_line / bytes_per_word, false),
+    Some(Cols::Bytes(cols)) => (cols / bytes_per_word, false),
+    Some(Cols::Auto) => (bytes_per_line / bytes_per_word, true),
+  };
+  let words_per_line = words_per_line.max(1);
+
+  let colors = gradient.into_map(eks.categorical, eks.simulate);
+  let ascii = ascii.into_opts(eks.simulate);
 
-  let mut gradient = eks.gradient.0;
-  if gradient.is_empty() {
-    gradient = vec![palette::named::BEIGE];
+  let mut calc_prefix = String::new();
+  for path in &eks.calc_file {
+    let src = fs::read_to_string(path).map_err(|e| {
+      io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+    })?;
+    calc_prefix.push_str(&src);
+    calc_prefix.push('\n');
   }
+  let parse_calc =
+    |src: &str| match calc::Calc::parse(&(calc_prefix.clone() + src)) {
+      Ok(calc) => calc,
+      Err(e) => {
+        eprintln!("eks: invalid formula: {e}");
+        exit(1);
+      }
+    };
+  let calc = parse_calc(&eks.calc);
+  let style = eks.style.as_deref().map(parse_calc);
 
-  let use_truecolor = eks
-    .force_truecolor
-    .unwrap_or_else(|| env::var_os("COLORTERM") == Some("truecolor".into()));
+  let is_tty = output.is_none() && io::stdout().is_terminal();
+  let depth = color::Depth::detect(when, is_tty);
 
-  render::RenderOpts {
+  let mut stdout = io::stdout();
+  let mut placeholder = input::Input::stream(io::empty());
+  let mut opts = render::RenderOpts {
     log2_base,
     bytes_per_word,
     words_per_line,
-    display_offset_start: eks.offset.unwrap_or(start_offset),
-    limit: eks.limit,
+    fit_width,
+    display_offset_start: eks.offset.unwrap_or(0),
+    regions: Vec::new(),
+    header: None,
+    format: eks.format,
     little_endian: eks.little_endian,
+    bit_fields,
+    bit_offset: eks.bit_offset,
+    bit_order: eks.bit_order,
+    lanes: eks.lanes,
 
-    gradient,
-    use_truecolor,
-    ascii: eks.ascii.0,
+    colors,
+    gradient_space,
+    depth,
+    background: eks.background,
+    legend: false,
+    ascii,
+    accents,
     color_single_glyphs: eks.color_single_glyphs,
     uppercase: eks.uppercase,
 
     row_label_style: eks.row_label_style,
-    calc: eks.calc,
-
-    r: input
-      .as_mut()
-      .map(|f| f as &mut dyn io::Read)
-      .unwrap_or(&mut io::stdin()),
-    w: output
-      .as_mut()
-      .map(|f| f as &mut dyn io::Write)
-      .unwrap_or(&mut io::stdout()),
-  }
-  .render()
+    row_label_base: eks.row_label_base,
+    relative_to: eks.relative_to,
+    calc,
+    style,
+    calc_strategy: eks.calc_strategy,
+    annotations: eks.annotate.map_or_else(Vec::new, |a| a.entries),
+    footnotes: eks.footnotes,
+    checksums: eks.checksum.map_or_else(Vec::new, |c| c.0),
+    line_checksums: eks.line_checksums,
+    jobs: 1,
+    sections: None,
+    compressed_offsets: eks.compressed_offsets,
+    follow: eks.follow,
+    redraw: is_tty,
+
+    r: &mut placeholder,
+    w: match &mut output {
+      Some(f) => f,
+      None => &mut stdout,
+    },
+  };
+
+  let count = inputs.len();
+  if eks.follow && (count > 1 || spans.len() > 1) {
+    eprintln!("eks: -f can only follow one input and range");
+    exit(1);
+  }
+  if eks.to.is_some() && (count > 1 || eks.follow) {
+    eprintln!("eks: --to can only write one input, and cannot be used with -f");
+    exit(1);
+  }
+  if eks.line_checksums && opts.checksums.is_empty() {
+    eprintln!("eks: --line-checksums needs --checksum");
+    exit(1);
+  }
+  if !opts.checksums.is_empty()
+    && (eks.to.is_some() || eks.format != render::Format::Text)
+  {
+    eprintln!("eks: --checksum cannot be used with --to or --format");
+    exit(1);
+  }
+  if eks.format != render::Format::Text && (count > 1 || eks.to.is_some()) {
+    eprintln!(
+      "eks: --format can only write one input, and cannot be used with --to"
+    );
+    exit(1);
+  }
+  for (i, (path, input, image)) in inputs.iter_mut().enumerate() {
+    // Counting back from the end of a stream reads all of it first, leaving
+    // nothing to follow.
+    let lookback = spans.iter().any(|s| s.lookback().is_some());
+    if eks.follow && lookback && !input.is_seekable() {
+      eprintln!("eks: -f can't count back from the end of a pipe");
+      exit(1);
+    }
+    let found = find_regions(input, &spans).and_then(|regions| {
+      match eks.sections || !eks.section.is_empty() {
+        true => find_sections(input, regions, &eks.section)
+          .map(|(regions, sections)| (regions, Some(sections))),
+        false => {
+          Ok((regions.into_iter().map(render::Region::new).collect(), None))
+        }
+      }
+    });
+    let (regions, sections) = match found {
+      Ok(found) => found,
+      Err(e) if count > 1 => {
+        eprintln!("eks: {}: {e}", input_name(path));
+        exit(1);
+      }
+      Err(e) => {
+        eprintln!("eks: {e}");
+        exit(1);
+      }
+    };
+
+    if i != 0 {
+      writeln!(opts.w)?;
+    }
+    if count > 1 {
+      let name = input_name(path);
+      opts.header = Some(match input.size() {
+        Some(size) => format!("{name} ({size} bytes)"),
+        None => name,
+      });
+    }
+
+    // Only regular files are worth splitting up by default; pipes tend to be
+    // slower than rendering them anyway.
+    opts.jobs = eks.jobs.unwrap_or_else(|| match input.size() {
+      Some(_) => thread::available_parallelism().map_or(1, |n| n.get()),
+      None => 1,
+    });
+    opts.legend = eks.legend && i + 1 == count;
+    // A followed input keeps growing past the end of the map.
+    let size = input.size().filter(|_| !eks.follow);
+    opts.regions = match (&image, &eks.address_map) {
+      (Some(map), _) => skip_gaps(map_addresses(regions, map, size), map),
+      (None, Some(map)) => map_addresses(regions, map, size),
+      (None, None) => regions,
+    };
+    if let Some(format) = eks.to {
+      let offset = opts.display_offset_start;
+      write_records(format, input, &opts.regions, offset, opts.w)?;
+      continue;
+    }
+    opts.sections = sections.filter(|_| eks.sections);
+    opts.r = input;
+    // Errors while reading, such as from corrupt compressed data, name the
+    // input they came from.
+    opts.render().map_err(|e| match e.kind() {
+      io::ErrorKind::BrokenPipe => e,
+      kind => io::Error::new(kind, format!("{}: {e}", input_name(path))),
+    })?;
+  }
+  Ok(())
+}
+
+/// Returns how to refer to the input at `path` in headers and errors.
+fn input_name(path: &Path) -> String {
+  match path == Path::new("-") {
+    true => "<stdin>".into(),
+    false => path.display().to_string(),
+  }
+}
+
+/// Reads a record file from `input`, returning the memory image it describes
+/// along with where each run of the image was loaded.
+fn load_image(
+  mut input: input::Input,
+  format: records::Format,
+) -> io::Result<(input::Input, Option<input::AddressMap>)> {
+  let mut src = String::new();
+  input.read_to_string(&mut src)?;
+  let image = records::Image::parse(format, &src)
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+  let (data, map) = image.flatten();
+  Ok((input::Input::memory(data), Some(map)))
+}
+
+/// Titles the regions that start after a gap in a memory image with how much
+/// of it was skipped.
+fn skip_gaps(
+  mut regions: Vec<render::Region>,
+  map: &input::AddressMap,
+) -> Vec<render::Region> {
+  for region in &mut regions {
+    let i = map
+      .entries
+      .iter()
+      .position(|(range, _)| range.start == region.range.start);
+    let Some(i) = i.filter(|&i| i > 0 && region.title.is_none()) else {
+      continue;
+    };
+    let (prev, prev_addr) = &map.entries[i - 1];
+    let gap = map.entries[i].1 - (prev_addr + (prev.end - prev.start));
+    region.title = Some(format!("{gap:#x} bytes skipped"));
+  }
+  regions
+}
+
+/// Writes the `regions` of `input` out as records, at the addresses their
+/// rows would be labeled with.
+fn write_records(
+  format: records::Format,
+  input: &mut input::Input,
+  regions: &[render::Region],
+  offset: u64,
+  w: &mut dyn io::Write,
+) -> io::Result<()> {
+  let mut records = records::Writer::new(format, w);
+  for region in regions {
+    input.seek_to(region.range.start)?;
+    let mut r = input.by_ref().take(region.range.end - region.range.start);
+    let mut addr = offset.checked_add(region.label_start).ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+          "address out of range: {offset:#x} + {:#x}",
+          region.label_start
+        ),
+      )
+    })?;
+    loop {
+      let mut chunk = Vec::new();
+      r.by_ref().take(1 << 16).read_to_end(&mut chunk)?;
+      if chunk.is_empty() {
+        break;
+      }
+      records.data(addr, &chunk)?;
+      addr += chunk.len() as u64;
+    }
+  }
+  records.finish()
+}
+
+/// Splits `regions` up where the mappings in `map` start and end, and labels
+/// the parts that are mapped with their addresses. Parts past `size`, the end
+/// of the input, are dropped.
+fn map_addresses(
+  regions: Vec<render::Region>,
+  map: &input::AddressMap,
+  size: Option<u64>,
+) -> Vec<render::Region> {
+  let bounds = map
+    .entries
+    .iter()
+    .map(|(r, _)| r.clone())
+    .collect::<Vec<_>>();
+  let mut mapped = Vec::new();
+  for region in regions {
+    let start = region.range.start;
+    let mut title = region.title;
+    for range in input::split(region.range, &bounds) {
+      if range.start != start && size.is_some_and(|size| range.start >= size) {
+        break;
+      }
+      mapped.push(render::Region {
+        label_start: map
+          .get(range.start)
+          .unwrap_or(region.label_start.wrapping_add(range.start - start)),
+        title: title.take(),
+        range,
+      });
+    }
+  }
+  mapped
+}
+
+/// Reads the section headers of `input`, and uses them to pick out the
+/// `named` sections to dump, or else to split up `regions` where sections
+/// start and end. Also returns the file ranges of every section.
+fn find_sections(
+  input: &mut input::Input,
+  regions: Vec<Range<u64>>,
+  named: &[String],
+) -> Result<(Vec<render::Region>, Vec<Range<u64>>), String> {
+  if !input.is_seekable() {
+    return Err("can't find the sections of a stream".into());
+  }
+  // The headers are read from wherever they are in the file, rather than
+  // reading in all of it.
+  let sections = sections::parse(&object::ReadCache::new(&mut *input))?;
+  let title = |s: &sections::Section| format!("{} at {:#x}", s.name, s.addr);
+
+  let regions = match named {
+    [] => regions
+      .into_iter()
+      .flat_map(|range| {
+        let bounds = sections.iter().map(|s| s.range.clone());
+        input::split(range, &bounds.collect::<Vec<_>>())
+      })
+      .map(|range| render::Region {
+        title: sections
+          .iter()
+          .find(|s| s.range.start == range.start)
+          .map(title),
+        ..render::Region::new(range)
+      })
+      .collect(),
+    named => named
+      .iter()
+      .map(|name| {
+        let section =
+          sections.iter().find(|s| &s.name == name).ok_or_else(|| {
+            let names = sections.iter().map(|s| &s.name[..]);
+            format!(
+              "no section named {name}; there are {}",
+              names.collect::<Vec<_>>().join(", ")
+            )
+          })?;
+        Ok(render::Region {
+          range: section.range.clone(),
+          label_start: section.addr,
+          title: Some(title(section)),
+        })
+      })
+      .collect::<Result<_, String>>()?,
+  };
+  let ranges = sections.into_iter().map(|s| s.range).collect();
+  Ok((regions, ranges))
+}
+
+/// Works out which parts of `input` the `spans` given on the command line
+/// refer to.
+fn find_regions(
+  input: &mut input::Input,
+  spans: &[input::Span],
+) -> Result<Vec<Range<u64>>, String> {
+  // Streams can't be seeked backwards, so to count from their end we hold
+  // onto as much of it as we need to look back over.
+  if let Some(lookback) = spans.iter().filter_map(|s| s.lookback()).max() {
+    input.buffer_tail(lookback).map_err(|e| e.to_string())?;
+  }
+
+  let regions = spans
+    .iter()
+    .map(|span| span.resolve(input.size()))
+    .collect::<Result<Vec<_>, _>>()?;
+  if !input.is_seekable() && regions.windows(2).any(|w| w[1].start < w[0].end) {
+    return Err("ranges of a stream must be in order and not overlap".into());
+  }
+  if let Some(first) = regions.first() {
+    if !input.is_seekable() && first.start < input.pos() {
+      return Err(format!(
+        "can't dump from {:#x}: only the end of a stream is kept when \
+         counting back from it",
+        first.start
+      ));
+    }
+  }
+  Ok(regions)
 }
 
 fn main() {
@@ -324,3 +1381,175 @@ fn main() {
     exit(1);
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use argh::FromArgValue;
+
+  /// Every pair of colors in the presets meant for color vision deficiencies
+  /// should stay apart under each of them.
+  #[test]
+  fn colorblind_presets_are_distinct() {
+    let ascii = |name| {
+      let ascii = MaybeAscii::from_arg_value(name).unwrap().0.unwrap();
+      ascii.colors().map(|(_, c)| c).to_vec()
+    };
+    let gradient = |name| Colors::from_arg_value(name).unwrap().map.colors();
+    let theme = theme::Theme::builtin("colorblind").unwrap();
+    let (_, _, theme_ascii, _) = theme_colors(&theme).unwrap();
+
+    let presets = [
+      ("-y okabe-ito", ascii("okabe-ito")),
+      ("-y tol-bright", ascii("tol-bright")),
+      ("-z okabe-ito", gradient("okabe-ito")),
+      ("-z tol-bright", gradient("tol-bright")),
+      ("-z tol-vibrant", gradient("tol-vibrant")),
+      (
+        "--theme colorblind",
+        theme_ascii.0.unwrap().colors().map(|(_, c)| c).to_vec(),
+      ),
+    ];
+    let visions = [
+      color::Deficiency::Protanopia,
+      color::Deficiency::Deuteranopia,
+      color::Deficiency::Tritanopia,
+    ];
+    for (name, colors) in presets {
+      for vision in visions {
+        for (i, &a) in colors.iter().enumerate() {
+          for &b in &colors[i + 1..] {
+            let diff = color::delta_e(vision.simulate(a), vision.simulate(b));
+            assert!(
+              diff >= color::MIN_DELTA_E,
+              "{name}: #{a:x} and #{b:x} are {diff:.1} apart with {vision:?}"
+            );
+          }
+        }
+      }
+    }
+  }
+
+  /// Offsets and lengths can be written in hex, like addresses everywhere
+  /// else.
+  #[test]
+  fn hex_options() {
+    let args = ["-o", "0x8000", "-s", "-0x10", "-l", "0x20", "file"];
+    let eks = Eks::from_args(&["0x"], &args).unwrap();
+    assert_eq!(eks.offset, Some(0x8000));
+    assert_eq!(eks.seek, Some(input::Pos::End(0x10)));
+    assert_eq!(eks.limit, Some(0x20));
+
+    let eks = Eks::from_args(&["0x"], &["-s", "16", "file"]).unwrap();
+    assert_eq!(eks.seek, Some(input::Pos::Start(16)));
+    assert!(Eks::from_args(&["0x"], &["-o", "0xg", "file"]).is_err());
+  }
+
+  /// Regions are split where address map entries start and end, and labeled
+  /// with the addresses they're mapped to.
+  #[test]
+  fn address_maps() {
+    let map = input::AddressMap::parse(
+      "0x10..0x20 0x8000
+       0x30+0x10  0x100",
+    )
+    .unwrap();
+    let regions = vec![render::Region::new(0..u64::MAX)];
+    let mapped = map_addresses(regions, &map, Some(0x38))
+      .into_iter()
+      .map(|r| (r.range, r.label_start))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      mapped,
+      [
+        (0x0..0x10, 0x0),
+        (0x10..0x20, 0x8000),
+        (0x20..0x30, 0x20),
+        (0x30..0x40, 0x100),
+      ]
+    );
+
+    // Regions that start partway into an entry are labeled from there.
+    let regions = vec![render::Region::new(0x18..0x34)];
+    let mapped = map_addresses(regions, &map, None)
+      .into_iter()
+      .map(|r| (r.range, r.label_start))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      mapped,
+      [
+        (0x18..0x20, 0x8008),
+        (0x20..0x30, 0x20),
+        (0x30..0x34, 0x100)
+      ]
+    );
+  }
+
+  #[test]
+  fn ranges() {
+    use input::Pos::*;
+    use input::SpanEnd::*;
+    let parse = |s| Ranges::from_arg_value(s).map(|r| r.0);
+    let span = |start, end| input::Span { start, end };
+
+    assert_eq!(
+      parse("0x10..0x20"),
+      Ok(vec![span(Start(16), At(Start(32)))])
+    );
+    assert_eq!(parse("-0x10.."), Ok(vec![span(End(16), Open)]));
+    assert_eq!(parse("..-8"), Ok(vec![span(Start(0), At(End(8)))]));
+    assert_eq!(parse(".."), Ok(vec![span(Start(0), Open)]));
+    assert_eq!(parse("-4+2"), Ok(vec![span(End(4), Len(2))]));
+    assert_eq!(
+      parse("0..4, 2..6,-1+1"),
+      Ok(vec![
+        span(Start(0), At(Start(4))),
+        span(Start(2), At(Start(6))),
+        span(End(1), Len(1)),
+      ])
+    );
+
+    assert!(parse("4").is_err());
+    assert!(parse("0..4,").is_err());
+    assert!(parse("x..4").is_err());
+    assert!(parse("4+-2").is_err());
+  }
+
+  /// Ranges may overlap or go backwards in inputs that can be seeked around
+  /// in, but not in streams.
+  #[test]
+  fn overlapping_ranges() {
+    let spans = Ranges::from_arg_value("0..8,4..12,-4..").unwrap().0;
+    let data = (0..16).collect::<Vec<u8>>();
+
+    let mut file = input::Input::memory(data.clone());
+    assert_eq!(
+      find_regions(&mut file, &spans),
+      Ok(vec![0..8, 4..12, 12..u64::MAX])
+    );
+
+    let mut stream = input::Input::stream(io::Cursor::new(data.clone()));
+    assert_eq!(
+      find_regions(&mut stream, &spans),
+      Err("ranges of a stream must be in order and not overlap".into())
+    );
+
+    // Counting back from the end of a stream keeps only that much of it.
+    let spans = Ranges::from_arg_value("0..4,-4..").unwrap().0;
+    let mut stream = input::Input::stream(io::Cursor::new(data.clone()));
+    assert_eq!(
+      find_regions(&mut stream, &spans),
+      Err(
+        "can't dump from 0x0: only the end of a stream is kept when counting \
+         back from it"
+          .into()
+      )
+    );
+    let spans = Ranges::from_arg_value("-8+4,-4..").unwrap().0;
+    let mut stream = input::Input::stream(io::Cursor::new(data));
+    assert_eq!(
+      find_regions(&mut stream, &spans),
+      Ok(vec![8..12, 12..u64::MAX])
+    );
+  }
+}
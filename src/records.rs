@@ -0,0 +1,465 @@
+// Copyright 2022 Miguel Young de la Sota
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Intel HEX and Motorola S-record files, which describe a memory image as
+//! lines of hex-encoded records.
+
+use std::io;
+
+use crate::input::AddressMap;
+
+/// How many bytes of data go in each record we write.
+const RECORD_LEN: usize = 16;
+
+/// A record file format.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Format {
+  IntelHex,
+  /// S-records with 16-bit addresses.
+  S19,
+  /// S-records with 24-bit addresses.
+  S28,
+  /// S-records with 32-bit addresses.
+  S37,
+}
+
+impl argh::FromArgValue for Format {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    match value.to_lowercase().as_str() {
+      "ihex" | "hex" | "intel-hex" => Ok(Self::IntelHex),
+      "s19" | "srec" => Ok(Self::S19),
+      "s28" => Ok(Self::S28),
+      "s37" => Ok(Self::S37),
+      _ => Err("expected `ihex`, `s19`, `s28`, or `s37`".into()),
+    }
+  }
+}
+
+/// A sparse memory image: runs of bytes at increasing, non-overlapping
+/// addresses.
+#[derive(Clone, Debug, Default)]
+pub struct Image {
+  pub runs: Vec<(u64, Vec<u8>)>,
+}
+
+impl Image {
+  /// Parses a record file. Any of the S-record formats can read any
+  /// S-record file.
+  pub fn parse(format: Format, src: &str) -> Result<Image, String> {
+    let mut records = Vec::new();
+    let mut base = 0;
+    for (i, line) in src.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() {
+        continue;
+      }
+      let err = |e: &str| format!("line {}: {e}", i + 1);
+      let record = match format {
+        Format::IntelHex => parse_ihex(line, &mut base),
+        _ => parse_srec(line),
+      };
+      match record.map_err(|e| err(&e))? {
+        Record::Data(addr, data) => records.push((addr, data)),
+        Record::Other => {}
+        Record::End => break,
+      }
+    }
+
+    // Records can come in any order, but adjacent ones are merged into a
+    // single run.
+    records.sort_by_key(|(addr, _)| *addr);
+    let mut runs = Vec::<(u64, Vec<u8>)>::new();
+    for (addr, data) in records {
+      match runs.last_mut() {
+        Some((start, run)) if *start + run.len() as u64 > addr => {
+          return Err(format!("data at {addr:#x} is given more than once"));
+        }
+        Some((start, run)) if *start + run.len() as u64 == addr => {
+          run.extend(data)
+        }
+        _ => runs.push((addr, data)),
+      }
+    }
+    Ok(Image { runs })
+  }
+
+  /// Lays the runs of the image out one after another, returning their bytes
+  /// and a map from where each run ended up to its load address.
+  pub fn flatten(self) -> (Vec<u8>, AddressMap) {
+    let mut data = Vec::new();
+    let mut map = AddressMap::default();
+    for (addr, run) in self.runs {
+      let start = data.len() as u64;
+      data.extend(run);
+      map.entries.push((start..data.len() as u64, addr));
+    }
+    (data, map)
+  }
+}
+
+enum Record {
+  Data(u64, Vec<u8>),
+  Other,
+  End,
+}
+
+/// Decodes a string of hex digit pairs.
+fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+  if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+    return Err("expected pairs of hex digits".into());
+  }
+  (0..hex.len())
+    .step_by(2)
+    .map(|i| {
+      u8::from_str_radix(&hex[i..i + 2], 16)
+        .map_err(|_| format!("`{}` is not a hex byte", &hex[i..i + 2]))
+    })
+    .collect()
+}
+
+/// Parses one Intel HEX record. `base` is the address set by the last
+/// extended address record, which data addresses are relative to.
+fn parse_ihex(line: &str, base: &mut u64) -> Result<Record, String> {
+  let hex = line
+    .strip_prefix(':')
+    .ok_or("Intel HEX records start with `:`")?;
+  let bytes = decode_hex(hex)?;
+  if bytes.len() < 5 || bytes.len() != 5 + bytes[0] as usize {
+    return Err("record length doesn't match its byte count".into());
+  }
+  if bytes.iter().fold(0u8, |a, &b| a.wrapping_add(b)) != 0 {
+    return Err("bad checksum".into());
+  }
+
+  let addr = u16::from_be_bytes([bytes[1], bytes[2]]) as u64;
+  let data = &bytes[4..bytes.len() - 1];
+  let value = || match data {
+    &[hi, lo] => Ok(u16::from_be_bytes([hi, lo]) as u64),
+    _ => Err("expected two bytes of data"),
+  };
+  match bytes[3] {
+    0x00 => return Ok(Record::Data(*base + addr, data.to_vec())),
+    0x01 => return Ok(Record::End),
+    0x02 => *base = value()? << 4,
+    0x04 => *base = value()? << 16,
+    // Start addresses don't affect the image.
+    0x03 | 0x05 => {}
+    ty => return Err(format!("unknown record type {ty:02X}")),
+  }
+  Ok(Record::Other)
+}
+
+/// Parses one S-record.
+fn parse_srec(line: &str) -> Result<Record, String> {
+  let (ty, hex) = line
+    .strip_prefix('S')
+    .and_then(|rest| Some((rest.chars().next()?, rest.get(1..)?)))
+    .ok_or("S-records start with `S` and a type")?;
+  let bytes = decode_hex(hex)?;
+  if bytes.is_empty() || bytes.len() != 1 + bytes[0] as usize {
+    return Err("record length doesn't match its byte count".into());
+  }
+  let sum = bytes.iter().fold(0u8, |a, &b| a.wrapping_add(b));
+  if sum != 0xff {
+    return Err("bad checksum".into());
+  }
+
+  let addr_len = match ty {
+    '0' | '1' | '5' | '9' => 2,
+    '2' | '6' | '8' => 3,
+    '3' | '7' => 4,
+    _ => return Err(format!("unknown record type S{ty}")),
+  };
+  if bytes.len() < 2 + addr_len {
+    return Err("record is too short for its address".into());
+  }
+  let addr = bytes[1..1 + addr_len]
+    .iter()
+    .fold(0u64, |a, &b| a << 8 | b as u64);
+  let data = &bytes[1 + addr_len..bytes.len() - 1];
+  match ty {
+    '1' | '2' | '3' => Ok(Record::Data(addr, data.to_vec())),
+    '7' | '8' | '9' => Ok(Record::End),
+    _ => Ok(Record::Other),
+  }
+}
+
+/// A kind of record that [`Writer`] writes. Its type code depends on the
+/// format.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Kind {
+  Data,
+  /// The upper 16 bits of the addresses of the Intel HEX data records that
+  /// follow.
+  ExtendedAddress,
+  /// The number of S-record data records, in 16 bits.
+  Count16,
+  /// The number of S-record data records, in 24 bits.
+  Count24,
+  End,
+}
+
+/// Writes records in some format, keeping track of what's needed to write
+/// them correctly.
+pub struct Writer<'a> {
+  format: Format,
+  w: &'a mut dyn io::Write,
+  /// The upper half of the address of the last Intel HEX record.
+  base: Option<u64>,
+  /// How many S-records have been written.
+  count: u64,
+}
+
+impl<'a> Writer<'a> {
+  pub fn new(format: Format, w: &'a mut dyn io::Write) -> Writer<'a> {
+    Writer {
+      format,
+      w,
+      base: None,
+      count: 0,
+    }
+  }
+
+  /// Returns the type code of `kind` records in this format.
+  fn type_code(&self, kind: Kind) -> u8 {
+    match (self.format, kind) {
+      (Format::IntelHex, Kind::Data) => 0x00,
+      (Format::IntelHex, Kind::End) => 0x01,
+      (Format::IntelHex, Kind::ExtendedAddress) => 0x04,
+      (Format::IntelHex, _) => unreachable!("{kind:?} is only for S-records"),
+
+      (_, Kind::Data) => self.addr_len() as u8 - 1,
+      (_, Kind::Count16) => 5,
+      (_, Kind::Count24) => 6,
+      (_, Kind::End) => 11 - self.addr_len() as u8,
+      (_, Kind::ExtendedAddress) => {
+        unreachable!("{kind:?} is only for Intel HEX")
+      }
+    }
+  }
+
+  /// Writes a single record of the given kind, address bytes, and data,
+  /// followed by its checksum.
+  fn record(&mut self, kind: Kind, addr: &[u8], data: &[u8]) -> io::Result<()> {
+    let ty = self.type_code(kind);
+    let mut bytes = Vec::new();
+    match self.format {
+      Format::IntelHex => {
+        bytes.push(data.len() as u8);
+        bytes.extend(addr);
+        bytes.push(ty);
+        bytes.extend(data);
+        let sum = bytes.iter().fold(0u8, |a, &b| a.wrapping_add(b));
+        bytes.push(sum.wrapping_neg());
+        write!(self.w, ":")?;
+      }
+      _ => {
+        bytes.push((addr.len() + data.len() + 1) as u8);
+        bytes.extend(addr);
+        bytes.extend(data);
+        let sum = bytes.iter().fold(0u8, |a, &b| a.wrapping_add(b));
+        bytes.push(!sum);
+        write!(self.w, "S{ty}")?;
+      }
+    }
+    for b in bytes {
+      write!(self.w, "{b:02X}")?;
+    }
+    writeln!(self.w)
+  }
+
+  /// The number of address bytes in S-records of this format.
+  fn addr_len(&self) -> usize {
+    match self.format {
+      Format::IntelHex | Format::S19 => 2,
+      Format::S28 => 3,
+      Format::S37 => 4,
+    }
+  }
+
+  /// Writes `data` to be loaded at `addr`.
+  pub fn data(&mut self, mut addr: u64, data: &[u8]) -> io::Result<()> {
+    let limit = match self.format {
+      Format::IntelHex => 1 << 32,
+      _ => 1 << (8 * self.addr_len()),
+    };
+    let end = addr.checked_add(data.len() as u64);
+    if end.is_none_or(|end| end > limit) {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+          "{:#x} is past the largest address {:?} records can hold",
+          addr.saturating_add(data.len() as u64 - 1),
+          self.format,
+        ),
+      ));
+    }
+
+    for mut chunk in data.chunks(RECORD_LEN) {
+      while !chunk.is_empty() {
+        // Intel HEX records can't cross a 64 KiB boundary, since only
+        // their lower 16 address bits are in the record itself.
+        let len = match self.format {
+          Format::IntelHex => {
+            chunk.len().min(0x10000 - (addr & 0xffff) as usize)
+          }
+          _ => chunk.len(),
+        };
+        let (now, rest) = chunk.split_at(len);
+        if self.format == Format::IntelHex {
+          let base = addr >> 16;
+          if self.base != Some(base) {
+            let upper = (base as u16).to_be_bytes();
+            self.record(Kind::ExtendedAddress, &[0, 0], &upper)?;
+            self.base = Some(base);
+          }
+          self.record(Kind::Data, &(addr as u16).to_be_bytes(), now)?;
+        } else {
+          let addr = &addr.to_be_bytes()[8 - self.addr_len()..];
+          self.record(Kind::Data, addr, now)?;
+          self.count += 1;
+        }
+        addr += len as u64;
+        chunk = rest;
+      }
+    }
+    Ok(())
+  }
+
+  /// Writes the records that end a file.
+  pub fn finish(mut self) -> io::Result<()> {
+    match self.format {
+      Format::IntelHex => self.record(Kind::End, &[0, 0], &[]),
+      _ => {
+        match self.count {
+          0..=0xffff => {
+            let count = (self.count as u16).to_be_bytes();
+            self.record(Kind::Count16, &count, &[])?
+          }
+          _ => {
+            self.record(Kind::Count24, &self.count.to_be_bytes()[5..], &[])?
+          }
+        }
+        let addr = vec![0; self.addr_len()];
+        self.record(Kind::End, &addr, &[])
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Writes each of `runs` as records in `format`.
+  fn write(format: Format, runs: &[(u64, &[u8])]) -> io::Result<String> {
+    let mut out = Vec::new();
+    let mut w = Writer::new(format, &mut out);
+    for &(addr, data) in runs {
+      w.data(addr, data)?;
+    }
+    w.finish()?;
+    Ok(String::from_utf8(out).unwrap())
+  }
+
+  #[test]
+  fn checksums() {
+    let image = Image::parse(Format::IntelHex, ":02001000AABB89").unwrap();
+    assert_eq!(image.runs, [(0x10, vec![0xaa, 0xbb])]);
+    let image = Image::parse(Format::S19, "S1050010AABB85").unwrap();
+    assert_eq!(image.runs, [(0x10, vec![0xaa, 0xbb])]);
+
+    let err = Image::parse(Format::IntelHex, ":02001000AABB88").unwrap_err();
+    assert_eq!(err, "line 1: bad checksum");
+    let err = Image::parse(Format::S19, "\nS1050010AABB84").unwrap_err();
+    assert_eq!(err, "line 2: bad checksum");
+  }
+
+  #[test]
+  fn extended_addresses() {
+    // Type 02 sets bits 4 through 19 of the address, and type 04 bits 16
+    // through 31.
+    let src = ":020000021200EA\n:02001000AABB89\n:00000001FF";
+    let image = Image::parse(Format::IntelHex, src).unwrap();
+    assert_eq!(image.runs, [(0x12010, vec![0xaa, 0xbb])]);
+
+    let src = ":020000040800F2\n:02001000AABB89\n:00000001FF";
+    let image = Image::parse(Format::IntelHex, src).unwrap();
+    assert_eq!(image.runs, [(0x0800_0010, vec![0xaa, 0xbb])]);
+
+    // Each one replaces the last.
+    let src = ":020000040800F2\n:020000021200EA\n:02001000AABB89";
+    let image = Image::parse(Format::IntelHex, src).unwrap();
+    assert_eq!(image.runs, [(0x12010, vec![0xaa, 0xbb])]);
+  }
+
+  #[test]
+  fn overlaps() {
+    // Out of order, but adjacent.
+    let src = ":01001200CC21\n:02001000AABB89";
+    let image = Image::parse(Format::IntelHex, src).unwrap();
+    assert_eq!(image.runs, [(0x10, vec![0xaa, 0xbb, 0xcc])]);
+
+    let src = ":02001000AABB89\n:01001100CC22";
+    let err = Image::parse(Format::IntelHex, src).unwrap_err();
+    assert_eq!(err, "data at 0x11 is given more than once");
+  }
+
+  #[test]
+  fn address_limits() {
+    for (format, top) in [
+      (Format::S19, 0xffff),
+      (Format::S28, 0xff_ffff),
+      (Format::S37, 0xffff_ffff),
+      (Format::IntelHex, 0xffff_ffff),
+    ] {
+      assert!(write(format, &[(top, &[1])]).is_ok(), "{format:?}");
+      let err = write(format, &[(top, &[1, 2])]).unwrap_err();
+      assert_eq!(
+        err.to_string(),
+        format!(
+          "{:#x} is past the largest address {format:?} records can hold",
+          top + 1
+        ),
+      );
+      // Addresses this high would overflow a u64 if not checked.
+      assert!(write(format, &[(u64::MAX, &[1, 2])]).is_err(), "{format:?}");
+    }
+  }
+
+  #[test]
+  fn round_trip() {
+    let data = (0..=255).collect::<Vec<u8>>();
+    let runs: &[(u64, &[u8])] = &[
+      (0x0, &data[..20]),
+      (0x100, &data[..1]),
+      // Crosses a 64 KiB boundary, which Intel HEX records can't.
+      (0xfff8, &data),
+      (0xfe_0000, &data[..3]),
+    ];
+    for format in [Format::IntelHex, Format::S19, Format::S28, Format::S37] {
+      let runs = match format {
+        Format::S19 => &runs[..2],
+        _ => runs,
+      };
+      let src = write(format, runs).unwrap();
+      let image = Image::parse(format, &src).unwrap();
+      let want = runs
+        .iter()
+        .map(|&(addr, data)| (addr, data.to_vec()))
+        .collect::<Vec<_>>();
+      assert_eq!(image.runs, want, "{format:?}:\n{src}");
+    }
+  }
+}
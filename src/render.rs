@@ -14,14 +14,26 @@
 
 //! Rendering code.
 
+use std::env;
 use std::io;
 use std::io::Read as _;
+use std::io::Write as _;
+use std::ops::Range;
+use std::thread;
+use std::time::Duration;
 
 use palette::IntoColor;
 use palette::Srgb;
 
+use serde_json::json;
+
+use crate::annotate::Annotation;
+use crate::calc::Compiled;
+use crate::checksum;
 use crate::color;
+use crate::color::Depth;
 use crate::color::TermColor;
+use crate::input::Input;
 
 const ALPHABET: &[u8] =
   b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ+/";
@@ -36,27 +48,188 @@ pub struct AsciiOpts {
   pub punct: Srgb<u8>,
 }
 
+impl AsciiOpts {
+  /// Returns the colors for each class of character, along with their names.
+  pub fn colors(&self) -> [(&'static str, Srgb<u8>); 5] {
+    [
+      ("uppercase", self.upper),
+      ("lowercase", self.lower),
+      ("digits", self.number),
+      ("punctuation", self.punct),
+      ("unprintable", self.unprintable),
+    ]
+  }
+
+  /// Replaces every color with `f(color)`.
+  pub fn recolor(&mut self, f: impl Fn(Srgb<u8>) -> Srgb<u8>) {
+    for c in [
+      &mut self.upper,
+      &mut self.lower,
+      &mut self.number,
+      &mut self.punct,
+      &mut self.unprintable,
+    ] {
+      *c = f(*c);
+    }
+  }
+}
+
+/// Colors for the parts of a dump other than the bytes themselves.
+pub struct Accents {
+  pub row_label: Option<Srgb<u8>>,
+  pub separator: Option<Srgb<u8>>,
+  /// The background for bytes highlighted by `--style`.
+  pub highlight: Srgb<u8>,
+  /// The color of the header above each input.
+  pub header: Srgb<u8>,
+}
+
+impl Accents {
+  /// Replaces every color with `f(color)`.
+  pub fn recolor(&mut self, f: impl Fn(Srgb<u8>) -> Srgb<u8>) {
+    self.row_label = self.row_label.map(&f);
+    self.separator = self.separator.map(&f);
+    self.highlight = f(self.highlight);
+    self.header = f(self.header);
+  }
+}
+
+/// How formula results are turned into colors.
+#[derive(Clone)]
+pub enum ColorMap {
+  /// Results are scaled onto a smooth gradient through these stops.
+  Gradient(Vec<color::Stop>),
+  /// Results are taken modulo the number of colors and used as an index.
+  Classes(Vec<Srgb<u8>>),
+  /// Results are looked up in a table; anything missing is left uncolored.
+  Table(Vec<(u64, Srgb<u8>)>),
+}
+
+impl ColorMap {
+  /// Returns the colors that results can be mapped to.
+  pub fn colors(&self) -> Vec<Srgb<u8>> {
+    match self {
+      ColorMap::Gradient(stops) => stops.iter().map(|s| s.color).collect(),
+      ColorMap::Classes(cs) => cs.clone(),
+      ColorMap::Table(t) => t.iter().map(|&(_, c)| c).collect(),
+    }
+  }
+
+  /// Replaces every color with `f(color)`.
+  pub fn recolor(&mut self, f: impl Fn(Srgb<u8>) -> Srgb<u8>) {
+    match self {
+      ColorMap::Gradient(stops) => {
+        stops.iter_mut().for_each(|s| s.color = f(s.color))
+      }
+      ColorMap::Classes(cs) => cs.iter_mut().for_each(|c| *c = f(*c)),
+      ColorMap::Table(t) => t.iter_mut().for_each(|(_, c)| *c = f(*c)),
+    }
+  }
+
+  /// Maps `value`, which is at most `max`, to an index into the palette
+  /// built for this map.
+  fn index(&self, value: u64, max: u64) -> Option<usize> {
+    match self {
+      ColorMap::Gradient(_) => {
+        Some((255.0 * (value as f64 / max as f64)) as usize)
+      }
+      ColorMap::Classes(cs) => Some((value % cs.len() as u64) as usize),
+      ColorMap::Table(t) => t.iter().position(|&(v, _)| v == value),
+    }
+  }
+}
+
 pub struct RenderOpts<'a> {
   pub log2_base: u32,
   pub bytes_per_word: u32,
   pub words_per_line: u32,
+  /// Whether to ignore `words_per_line` and fit lines to the terminal.
+  pub fit_width: bool,
   pub little_endian: bool,
+  /// In base 2, the widths of the bit fields that words are split into,
+  /// repeating from `bit_offset` bits into each word.
+  pub bit_fields: Option<Vec<u32>>,
+  pub bit_offset: u32,
+  pub bit_order: BitOrder,
+  /// How many bytes of each word formulas are evaluated on at once; by
+  /// default, the fewest that are a whole number of glyphs wide.
+  pub lanes: Option<u32>,
+  /// Added to every row label.
   pub display_offset_start: u64,
-  pub limit: u64,
+  /// The parts of the input to dump, in order.
+  pub regions: Vec<Region>,
+  /// A line to print in bold before the dump, such as the input's name.
+  pub header: Option<String>,
+  pub format: Format,
 
-  pub gradient: Vec<Srgb<u8>>,
-  pub use_truecolor: bool,
+  pub colors: ColorMap,
+  pub gradient_space: color::Space,
+  pub depth: Depth,
+  pub background: bool,
+  pub legend: bool,
   pub color_single_glyphs: bool,
   pub ascii: Option<AsciiOpts>,
+  pub accents: Accents,
   pub uppercase: bool,
 
   pub row_label_style: RowLabelStyle,
+  pub row_label_base: RowLabelBase,
+  /// If set, row labels count from here, and are negative before it.
+  pub relative_to: Option<u64>,
   pub calc: crate::calc::Calc,
+  pub style: Option<crate::calc::Calc>,
+  pub calc_strategy: crate::calc::Strategy,
+
+  /// Parts of the input to highlight and write notes beside.
+  pub annotations: Vec<Annotation>,
+  /// Whether to number the notes of `annotations` and write them after the
+  /// dump, rather than beside the lines they're on.
+  pub footnotes: bool,
+  /// Checksums to write after the dump, of each region and of all of them.
+  pub checksums: Vec<checksum::Algorithm>,
+  /// Whether to also write `checksums` of each line, after the line.
+  pub line_checksums: bool,
+
+  /// How many threads to render with.
+  pub jobs: usize,
+  /// If set, bytes are colored by which of these parts of the input they're
+  /// in, rather than by `calc`.
+  pub sections: Option<Vec<Range<u64>>>,
+  /// Whether to label rows of a compressed input with roughly where they
+  /// came from in the compressed data.
+  pub compressed_offsets: bool,
+  /// Whether to keep waiting for more input at the end, like `tail -f`.
+  pub follow: bool,
+  /// Whether the output is a terminal, where a partial line can be drawn
+  /// while following and redrawn once more of it arrives.
+  pub redraw: bool,
 
-  pub r: &'a mut dyn io::Read,
+  pub r: &'a mut Input,
   pub w: &'a mut dyn io::Write,
 }
 
+/// A part of the input to dump.
+pub struct Region {
+  pub range: Range<u64>,
+  /// The row label for the start of the region.
+  pub label_start: u64,
+  /// A line to print before the region, in place of the usual `--` between
+  /// regions.
+  pub title: Option<String>,
+}
+
+impl Region {
+  /// Makes a region labeled with its offsets into the input.
+  pub fn new(range: Range<u64>) -> Region {
+    Region {
+      label_start: range.start,
+      range,
+      title: None,
+    }
+  }
+}
+
+#[derive(Copy, Clone)]
 pub enum RowLabelStyle {
   None,
   Byte,
@@ -64,6 +237,70 @@ pub enum RowLabelStyle {
   Line,
 }
 
+/// The base row labels are written in.
+#[derive(Copy, Clone)]
+pub enum RowLabelBase {
+  Hex,
+  Decimal,
+  Octal,
+  /// Whatever base the bytes are printed in.
+  Dump,
+}
+
+impl argh::FromArgValue for RowLabelBase {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    match value.to_lowercase().as_str() {
+      "hex" | "16" => Ok(Self::Hex),
+      "dec" | "decimal" | "10" => Ok(Self::Decimal),
+      "oct" | "octal" | "8" => Ok(Self::Octal),
+      "dump" | "same" => Ok(Self::Dump),
+      _ => Err("expected `hex`, `dec`, `oct`, or `dump`".into()),
+    }
+  }
+}
+
+/// What the dump is written as.
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub enum Format {
+  /// Lines of colored text, for a terminal.
+  #[default]
+  Text,
+  /// A JSON array with an object for each line, giving its glyphs and their
+  /// colors.
+  Json,
+  /// The same objects as `Json`, one per line of output.
+  JsonLines,
+}
+
+impl argh::FromArgValue for Format {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    match value.to_lowercase().as_str() {
+      "text" => Ok(Self::Text),
+      "json" => Ok(Self::Json),
+      "jsonl" | "json-lines" | "ndjson" => Ok(Self::JsonLines),
+      _ => Err("expected `text`, `json`, or `jsonl`".into()),
+    }
+  }
+}
+
+/// The order the bits of each byte are drawn in, in base 2.
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub enum BitOrder {
+  #[default]
+  Msb,
+  Lsb,
+}
+
+impl argh::FromArgValue for BitOrder {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    match value.to_lowercase().as_str() {
+      "msb" | "msb-first" | "msb0" => Ok(Self::Msb),
+      "lsb" | "lsb-first" | "lsb0" => Ok(Self::Lsb),
+      _ => Err("expected `msb` or `lsb`".into()),
+    }
+  }
+}
+
 impl argh::FromArgValue for RowLabelStyle {
   fn from_arg_value(value: &str) -> Result<Self, String> {
     match value.to_lowercase().as_str() {
@@ -76,235 +313,1785 @@ impl argh::FromArgValue for RowLabelStyle {
   }
 }
 
+/// A palette, resolved to the colors the terminal supports.
+enum Colors {
+  Ansi16(Vec<usize>),
+  Xterm256(Vec<usize>),
+  True(Vec<Srgb<u8>>),
+}
+
+impl Colors {
+  /// Resolves `map` for a terminal with `depth`. Gradients are sampled at 256
+  /// points.
+  fn new(map: &ColorMap, space: color::Space, depth: Depth) -> Colors {
+    let table = match depth {
+      Depth::None | Depth::True => {
+        return Colors::True(match map {
+          ColorMap::Gradient(stops) => color::make_gradient(stops, 256, space),
+          map => map.colors(),
+        })
+      }
+      Depth::Ansi16 => color::ANSI16_PALETTE,
+      Depth::Xterm256 => color::XTERM256_PALETTE,
+    };
+
+    let quanta = table
+      .iter()
+      .map(|&x| Srgb::from_components(x).into_format::<f32>().into_color())
+      .collect::<Vec<_>>();
+    let quantized = match map {
+      ColorMap::Gradient(stops) => {
+        color::make_quantized_gradient(stops, 256, space, &quanta)
+      }
+      map => color::quantize_rgb(map.colors(), &quanta),
+    };
+    match depth {
+      Depth::Ansi16 => Colors::Ansi16(quantized),
+      _ => Colors::Xterm256(quantized),
+    }
+  }
+
+  fn len(&self) -> usize {
+    match self {
+      Colors::Ansi16(cs) | Colors::Xterm256(cs) => cs.len(),
+      Colors::True(cs) => cs.len(),
+    }
+  }
+
+  fn term_color(&self, idx: usize) -> TermColor {
+    match self {
+      Colors::Ansi16(cs) if cs[idx] < 8 => TermColor::Dark(cs[idx] as u8),
+      Colors::Ansi16(cs) => TermColor::Bright(cs[idx] as u8 - 8),
+      Colors::Xterm256(cs) => TermColor::Index(cs[idx] + 16),
+      Colors::True(cs) => TermColor::Rgb(cs[idx]),
+    }
+  }
+
+  fn term_color_or_reset(&self, idx: Option<usize>) -> TermColor {
+    idx.map(|i| self.term_color(i)).unwrap_or(TermColor::Reset)
+  }
+
+  fn rgb(&self, idx: usize) -> Srgb<u8> {
+    match self {
+      Colors::Ansi16(cs) => {
+        Srgb::from_components(color::ANSI16_PALETTE[cs[idx]])
+      }
+      Colors::Xterm256(cs) => {
+        Srgb::from_components(color::XTERM256_PALETTE[cs[idx]])
+      }
+      Colors::True(cs) => cs[idx],
+    }
+  }
+
+  /// Returns black or white, whichever is more legible on `idx`.
+  fn contrasting(&self, idx: usize) -> TermColor {
+    match (self, color::is_light(self.rgb(idx))) {
+      (Colors::Ansi16(_), true) => TermColor::Dark(0),
+      (Colors::Ansi16(_), false) => TermColor::Bright(7),
+      (Colors::Xterm256(_), true) => TermColor::Index(16),
+      (Colors::Xterm256(_), false) => TermColor::Index(231),
+      (Colors::True(_), true) => TermColor::Rgb(palette::named::BLACK),
+      (Colors::True(_), false) => TermColor::Rgb(palette::named::WHITE),
+    }
+  }
+
+  /// Draws a strip of `width` cells sampled evenly from these colors, or one
+  /// swatch per color for discrete maps.
+  fn strip(
+    &self,
+    map: &ColorMap,
+    width: usize,
+    esc: bool,
+    w: &mut dyn io::Write,
+  ) -> io::Result<()> {
+    match map {
+      ColorMap::Gradient(_) => {
+        for i in 0..width {
+          if esc {
+            self.term_color(i * self.len() / width).fg(w)?;
+          }
+          write!(w, "█")?;
+        }
+      }
+      _ => {
+        for i in 0..self.len() {
+          if esc {
+            self.term_color(i).fg(w)?;
+          }
+          write!(w, "██")?;
+          if esc {
+            TermColor::Reset.fg(w)?;
+          }
+          match map {
+            ColorMap::Table(t) => write!(w, " {:#x}  ", t[i].0)?,
+            _ => write!(w, " {i}  ")?,
+          }
+        }
+      }
+    }
+    if esc {
+      TermColor::Reset.fg(w)?;
+    }
+    Ok(())
+  }
+}
+
+/// Roughly how much input is rendered at once, on each thread.
+const SEGMENT_LEN: usize = 1 << 16;
+
+/// How much output is buffered before it's written out.
+const OUT_BUF_LEN: usize = 1 << 16;
+
+/// How long to wait for more input at the end of a followed file.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The width of the strip drawn by `--legend` and `--preview-gradient`.
+const STRIP_WIDTH: usize = 64;
+
+/// Draws `map` as it would look in truecolor, 256 colors, and 16 colors, so
+/// that they can be compared.
+pub fn preview(
+  map: &ColorMap,
+  space: color::Space,
+  w: &mut dyn io::Write,
+) -> io::Result<()> {
+  for (name, depth) in [
+    ("truecolor", Depth::True),
+    ("256", Depth::Xterm256),
+    ("16", Depth::Ansi16),
+  ] {
+    write!(w, "{name:>9}  ")?;
+    Colors::new(map, space, depth).strip(map, STRIP_WIDTH, true, w)?;
+    writeln!(w)?;
+  }
+  Ok(())
+}
+
 impl RenderOpts<'_> {
+  /// Draws a legend for the color map, labeling each gradient stop with the
+  /// formula result it corresponds to, out of a maximum of `max`.
+  fn render_legend(
+    &mut self,
+    colors: &Colors,
+    max: u64,
+    esc: bool,
+  ) -> io::Result<()> {
+    writeln!(self.w)?;
+    colors.strip(&self.colors, STRIP_WIDTH, esc, self.w)?;
+    writeln!(self.w)?;
+
+    let ColorMap::Gradient(stops) = &self.colors else {
+      return Ok(());
+    };
+    let digits = format!("{max:x}").len();
+    let mut labels = String::new();
+    for stop in stops {
+      let value = (stop.pos as f64 * max as f64).round() as u64;
+      let label = format!("{value:#0width$x}", width = digits + 2);
+      // Labels are right-aligned at the end of the strip, and skipped if they
+      // would run into the previous one.
+      let col = ((stop.pos as f64 * STRIP_WIDTH as f64) as usize)
+        .min(STRIP_WIDTH.saturating_sub(label.len()));
+      let len = labels.chars().count();
+      if col < len + 1 && len != 0 {
+        continue;
+      }
+      labels.extend(std::iter::repeat_n(' ', col - len));
+      labels.push_str(&label);
+    }
+    writeln!(self.w, "{labels}")
+  }
+
   pub fn render(&mut self) -> io::Result<()> {
-    let base = 1u64 << self.log2_base;
+    let mut renderer = Renderer::new(self)?;
+
+    // Row labels are all padded to the width of the widest one, if we know
+    // what that will be.
+    for region in &self.regions {
+      let range = &region.range;
+      let end = self.r.size().map_or(range.end, |size| size.min(range.end));
+      if end == u64::MAX || end < range.start {
+        continue;
+      }
+      let first = self.display_offset_start.wrapping_add(region.label_start);
+      let last = first.wrapping_add((end - range.start).saturating_sub(1));
+      renderer.label_width = renderer
+        .label_width
+        .max(renderer.label_digits(first))
+        .max(renderer.label_digits(last));
+    }
+    if let Some(width) = terminal_width().filter(|_| self.fit_width) {
+      renderer.fit(width);
+    }
+    let mut out = io::BufWriter::with_capacity(OUT_BUF_LEN, &mut *self.w);
+    let text = self.format == Format::Text;
+    if self.format == Format::Json {
+      out.write_all(b"[\n")?;
+    }
+    if let Some(header) = self.header.as_ref().filter(|_| text) {
+      if renderer.esc {
+        out.write_all(b"\x1b[1m")?;
+        renderer.header_color.fg(&mut out)?;
+      }
+      out.write_all(header.as_bytes())?;
+      if renderer.esc {
+        out.write_all(b"\x1b[22m")?;
+        TermColor::Reset.fg(&mut out)?;
+      }
+      writeln!(out)?;
+    }
+    // Checksums are kept of each region, and of all of them together.
+    let start = |algorithms: &[checksum::Algorithm]| {
+      algorithms.iter().map(|a| a.start()).collect::<Vec<_>>()
+    };
+    let mut checksums = Vec::new();
+    let mut totals = start(&self.checksums);
+    for (i, region) in self.regions.iter().enumerate() {
+      if text && (i != 0 || region.title.is_some()) {
+        if let Some(color) = renderer.separator_color {
+          color.fg(&mut out)?;
+        }
+        match &region.title {
+          Some(title) => write!(out, "-- {title} --")?,
+          None => out.write_all(b"--")?,
+        }
+        if renderer.esc {
+          TermColor::Reset.fg(&mut out)?;
+        }
+        writeln!(out)?;
+      }
+      let range = &region.range;
+      self.r.seek_to(range.start)?;
+      renderer.region_start = range.start;
+      renderer.display_offset_start =
+        self.display_offset_start.wrapping_add(region.label_start);
+      let len = range.end - range.start;
+      // Partial lines can't be taken back once they're written as JSON.
+      let redraw = self.redraw && text;
+      let mut sums = start(&self.checksums);
+      let mut seen = |data: &[u8]| {
+        sums
+          .iter_mut()
+          .chain(&mut totals)
+          .for_each(|s| s.update(data))
+      };
+      let grows = self.r.can_grow();
+      let (r, jobs) = (&mut *self.r, self.jobs);
+      let rendered = match self.follow {
+        true => renderer.follow(r, len, grows, redraw, &mut seen, &mut out)?,
+        false => renderer.region(r, len, jobs, &mut seen, &mut out)?,
+      };
+      renderer.json_started |= rendered > 0;
+      let start = renderer.display_offset_start;
+      checksums.push((start..start.wrapping_add(rendered), sums));
+    }
+    if self.footnotes && text {
+      renderer.render_footnotes(&mut out)?;
+    }
+    if !self.checksums.is_empty() {
+      render_checksums(&self.checksums, checksums, totals, &mut out)?;
+    }
+    if self.format == Format::Json {
+      out.write_all(b"]\n")?;
+    }
+    out.flush()?;
+    drop(out);
+
+    if self.legend && text {
+      let max = match self.color_single_glyphs {
+        true => (1 << self.log2_base) - 1,
+        false => u64::MAX >> (64 - 8 * renderer.lane_len),
+      };
+      self.render_legend(&renderer.colors, max, renderer.esc)?;
+    }
+    Ok(())
+  }
+}
+
+/// Writes the checksums of each region, as labeled by its row labels, and,
+/// if there's more than one, the checksums of all of them together.
+fn render_checksums(
+  algorithms: &[checksum::Algorithm],
+  regions: Vec<(Range<u64>, Vec<checksum::State>)>,
+  totals: Vec<checksum::State>,
+  out: &mut dyn io::Write,
+) -> io::Result<()> {
+  let mut rows = Vec::new();
+  let many = regions.len() > 1;
+  for (range, sums) in regions {
+    let range = format!("{:#x}..{:#x}", range.start, range.end);
+    rows.extend(
+      algorithms
+        .iter()
+        .zip(sums)
+        .map(|(a, s)| (a, range.clone(), s)),
+    );
+  }
+  if many {
+    rows.extend(
+      algorithms
+        .iter()
+        .zip(totals)
+        .map(|(a, s)| (a, "all".into(), s)),
+    );
+  }
+
+  let name_width = rows.iter().map(|(a, _, _)| a.name().len()).max();
+  let range_width = rows.iter().map(|(_, r, _)| r.len()).max();
+  writeln!(out)?;
+  for (algorithm, range, sum) in rows {
+    writeln!(
+      out,
+      "{:name_width$}  {range:range_width$}  {}",
+      algorithm.name(),
+      sum.finish(),
+      name_width = name_width.unwrap_or(0),
+      range_width = range_width.unwrap_or(0),
+    )?;
+  }
+  Ok(())
+}
 
+/// Returns the width of the terminal, if there is one.
+fn terminal_width() -> Option<usize> {
+  if let Some((width, _)) = terminal_size::terminal_size() {
+    return Some(width.0 as usize);
+  }
+  env::var("COLUMNS").ok()?.parse().ok()
+}
+
+/// Returns the widths of the bit fields in a word: a leading field of
+/// `offset` bits, if any, and then `widths` over and over.
+fn field_widths(offset: u32, widths: &[u32]) -> impl Iterator<Item = u32> + '_ {
+  (offset > 0)
+    .then_some(offset)
+    .into_iter()
+    .chain(widths.iter().copied().cycle())
+}
+
+/// Returns which of the ASCII colors `b` is drawn in: unprintable,
+/// uppercase, lowercase, digit, or punctuation.
+fn ascii_class(b: u8) -> usize {
+  if b.is_ascii_uppercase() {
+    1
+  } else if b.is_ascii_lowercase() {
+    2
+  } else if b.is_ascii_digit() {
+    3
+  } else if b.is_ascii_punctuation() {
+    4
+  } else {
+    0
+  }
+}
+
+/// Reads up to `len` bytes, stopping early only at the end of the input or
+/// once `remaining` runs out.
+fn read_segment(
+  r: &mut dyn io::Read,
+  len: usize,
+  remaining: &mut u64,
+) -> io::Result<Vec<u8>> {
+  let len = len.min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+  let mut data = Vec::with_capacity(len);
+  r.take(len as u64).read_to_end(&mut data)?;
+  *remaining -= data.len() as u64;
+  Ok(data)
+}
+
+/// How a lane of a word is colored, which all of its glyphs start from.
+#[derive(Copy, Clone)]
+struct Lane {
+  /// Where the lane is in its word, as seen by formulas.
+  pos: u64,
+  /// What the lane's color was picked with: a formula result, or the
+  /// number of the section it's in.
+  key: Option<u64>,
+  idx: Option<usize>,
+  style: Option<color::Style>,
+  highlight: Option<TermColor>,
+  in_section: bool,
+}
+
+/// A glyph of a word, and how it's colored.
+#[derive(Copy, Clone)]
+struct Glyph {
+  /// Which digit of the alphabet the glyph is.
+  digit: u8,
+  /// Whether a bit field starts here, so that a separator goes before it.
+  field_start: bool,
+  key: Option<u64>,
+  /// The formula result or bit field the glyph's color comes from.
+  value: Option<u64>,
+  idx: Option<usize>,
+  style: Option<color::Style>,
+  highlight: Option<TermColor>,
+}
+
+/// Buffers for laying out words, reused from one word to the next.
+#[derive(Default)]
+struct WordBuf {
+  display: Vec<u8>,
+  lanes: Vec<Lane>,
+  glyphs: Vec<Glyph>,
+}
+
+/// Everything needed to format lines, worked out ahead of time.
+///
+/// A line only depends on its own bytes and offset, so any run of whole lines
+/// can be rendered independently of the others, including on another thread.
+struct Renderer {
+  log2_base: u32,
+  /// The fewest bytes that are a whole number of glyphs wide.
+  chunk_len: u32,
+  glyphs_per_word: u32,
+  /// How many bytes of a word formulas see at once.
+  lane_len: u32,
+  bytes_per_word: u32,
+  words_per_line: u32,
+  fit_width: bool,
+  little_endian: bool,
+  bit_fields: Option<Vec<u32>>,
+  bit_offset: u32,
+  bit_order: BitOrder,
+  /// How many field separators are drawn in each whole word.
+  seps_per_word: u32,
+  display_offset_start: u64,
+  compressed_offsets: Option<u64>,
+  /// Where in the input the region being dumped starts.
+  region_start: u64,
+  sections: Option<Vec<Range<u64>>>,
+  color_single_glyphs: bool,
+  alphabet: &'static [u8],
+  row_label_style: RowLabelStyle,
+  row_label_base: RowLabelBase,
+  relative_to: Option<u64>,
+  /// The fewest digits to write row labels with.
+  label_width: usize,
+  format: Format,
+  /// Whether any JSON objects have been written yet, as of the start of the
+  /// region being dumped.
+  json_started: bool,
+
+  esc: bool,
+  background: bool,
+  map: ColorMap,
+  colors: Colors,
+  fg_escapes: Vec<Vec<u8>>,
+  ascii_colors: Option<Colors>,
+  row_label_color: Option<TermColor>,
+  separator_color: Option<TermColor>,
+  header_color: TermColor,
+  highlight_color: TermColor,
+  annotations: Vec<Annotation>,
+  /// The color each of `annotations` is highlighted with.
+  annotation_colors: Vec<TermColor>,
+  /// If notes are written as footnotes, each annotation's footnote number.
+  /// Only annotations with a note on one of the regions get one, so that the
+  /// numbers that are shown have no gaps.
+  footnotes: Option<Vec<Option<usize>>>,
+  /// Checksums to write at the end of each line.
+  line_checksums: Vec<checksum::Algorithm>,
+
+  byte_calc: Compiled,
+  glyph_calc: Compiled,
+  style_calc: Option<Compiled>,
+}
+
+impl Renderer {
+  fn new(opts: &RenderOpts) -> io::Result<Renderer> {
     // lcm(base, 8) / 8
-    let chunk_len = match self.log2_base {
+    let chunk_len = match opts.log2_base {
       3 => 3,
       5 => 5,
       6 => 3,
       _ => 1,
     };
-    let glyphs_per_byte = (8 * chunk_len) / self.log2_base;
 
-    enum Colors {
-      Quantized(Vec<usize>),
-      True(Vec<Srgb<u8>>),
+    // Escapes are only written at all if the terminal supports color, but
+    // JSON always has colors.
+    let esc = opts.depth != Depth::None;
+    let colored = esc || opts.format != Format::Text;
+    let resolve =
+      |map: &ColorMap| Colors::new(map, opts.gradient_space, opts.depth);
+    let colors = resolve(&opts.colors);
+    let ascii_colors = opts.ascii.as_ref().map(|ac| {
+      let classes =
+        vec![ac.unprintable, ac.upper, ac.lower, ac.number, ac.punct];
+      resolve(&ColorMap::Classes(classes))
+    });
+    let accent =
+      |c: Srgb<u8>| resolve(&ColorMap::Classes(vec![c])).term_color(0);
+
+    // Foreground escapes are by far the most common, so they're formatted
+    // ahead of time.
+    let fg_escapes = (0..colors.len())
+      .map(|i| {
+        let mut escape = Vec::new();
+        colors.term_color(i).fg(&mut escape).map(|_| escape)
+      })
+      .collect::<io::Result<Vec<_>>>()?;
+
+    let lane_len = opts.lanes.unwrap_or(chunk_len);
+    let lanes_per_word = opts.bytes_per_word.div_ceil(lane_len);
+    let strategy = opts.calc_strategy;
+
+    let seps_per_word = match &opts.bit_fields {
+      Some(widths) => {
+        let word_bits = 8 * opts.bytes_per_word;
+        let fields = field_widths(opts.bit_offset, widths)
+          .scan(0, |at, width| {
+            let start = *at;
+            *at += width;
+            Some(start)
+          })
+          .take_while(|&start| start < word_bits)
+          .count();
+        fields.saturating_sub(1) as u32
+      }
+      None => 0,
+    };
+
+    Ok(Renderer {
+      log2_base: opts.log2_base,
+      chunk_len,
+      glyphs_per_word: (8 * opts.bytes_per_word).div_ceil(opts.log2_base),
+      lane_len,
+      bytes_per_word: opts.bytes_per_word,
+      words_per_line: opts.words_per_line,
+      fit_width: opts.fit_width && opts.format == Format::Text,
+      little_endian: opts.little_endian,
+      bit_fields: opts.bit_fields.clone(),
+      bit_offset: opts.bit_offset,
+      bit_order: opts.bit_order,
+      seps_per_word,
+      display_offset_start: opts.display_offset_start,
+      compressed_offsets: opts
+        .compressed_offsets
+        .then_some(opts.display_offset_start),
+      region_start: 0,
+      sections: opts.sections.clone(),
+      color_single_glyphs: opts.color_single_glyphs,
+      // Code in main() stops -u from being mixed with base64.
+      alphabet: if opts.uppercase {
+        ALPHABET_UPPER
+      } else {
+        ALPHABET
+      },
+      row_label_style: opts.row_label_style,
+      row_label_base: opts.row_label_base,
+      relative_to: opts.relative_to,
+      label_width: 8,
+      format: opts.format,
+      json_started: false,
+
+      esc,
+      background: colored && opts.background,
+      map: opts.colors.clone(),
+      colors,
+      fg_escapes,
+      ascii_colors,
+      row_label_color: opts.accents.row_label.filter(|_| esc).map(accent),
+      separator_color: opts.accents.separator.filter(|_| esc).map(accent),
+      header_color: accent(opts.accents.header),
+      highlight_color: accent(opts.accents.highlight),
+      annotations: opts.annotations.clone(),
+      annotation_colors: opts
+        .annotations
+        .iter()
+        .map(|a| accent(a.color.unwrap_or(opts.accents.highlight)))
+        .collect(),
+      footnotes: opts.footnotes.then(|| {
+        let mut numbered = 0;
+        let shown = |a: &Annotation| {
+          a.note.is_some()
+            && opts.regions.iter().any(|r| {
+              r.range.start < a.range.end && a.range.start < r.range.end
+            })
+        };
+        let number = |a| {
+          shown(a).then(|| {
+            numbered += 1;
+            numbered
+          })
+        };
+        opts.annotations.iter().map(number).collect()
+      }),
+      line_checksums: match opts.line_checksums {
+        true => opts.checksums.clone(),
+        false => Vec::new(),
+      },
+
+      byte_calc: opts.calc.compile(lane_len * 8, lanes_per_word, strategy),
+      glyph_calc: opts.calc.compile(opts.log2_base, lanes_per_word, strategy),
+      style_calc: opts
+        .style
+        .as_ref()
+        .filter(|_| colored)
+        .map(|style| style.compile(lane_len * 8, lanes_per_word, strategy)),
+    })
+  }
+
+  /// Picks the most words per line that fit in `width` columns, and at least
+  /// one.
+  fn fit(&mut self, width: usize) {
+    let label = match self.label_value(0) {
+      None => 0,
+      Some(_) => {
+        let (prefix, _, _) = self.label_radix();
+        let sign = self.relative_to.map_or(0, |_| 1);
+        sign + prefix.len() + self.label_width + ":  ".len()
+      }
+    };
+    let (gutter, gutter_per_word) = match self.ascii_colors {
+      Some(_) => ("  ||".len(), self.bytes_per_word as usize),
+      None => (0, 0),
+    };
+    let sums = self.line_checksums.iter().map(|a| "  ".len() + a.hex_len());
+    let word = (self.glyphs_per_word + self.seps_per_word) as usize;
+
+    // Every word but the first has a space before it.
+    let per_word = word + 1 + gutter_per_word;
+    let room = (width + 1).saturating_sub(label + gutter + sums.sum::<usize>());
+    self.words_per_line = (room / per_word).max(1) as u32;
+  }
+
+  /// Splits a word, given in the order its bytes are drawn, into bit fields.
+  /// Returns, for each bit in the order it's drawn, whether a field starts
+  /// there, and the value and color of the field.
+  fn word_fields(
+    &self,
+    widths: &[u32],
+    word: &[u8],
+  ) -> Vec<(bool, u64, Option<usize>)> {
+    let bits = word
+      .iter()
+      .flat_map(|&byte| {
+        (0..8).map(move |i| match self.bit_order {
+          BitOrder::Msb => byte >> (7 - i) & 1,
+          BitOrder::Lsb => byte >> i & 1,
+        })
+      })
+      .collect::<Vec<_>>();
+
+    let mut fields = Vec::with_capacity(bits.len());
+    for width in field_widths(self.bit_offset, widths) {
+      let start = fields.len();
+      if start == bits.len() {
+        break;
+      }
+      let field = &bits[start..(start + width as usize).min(bits.len())];
+      // The first bit drawn is the most significant one, unless bits are
+      // drawn LSB first.
+      let value = match self.bit_order {
+        BitOrder::Msb => field.iter().fold(0, |v, &b| v << 1 | b as u64),
+        BitOrder::Lsb => field.iter().rev().fold(0, |v, &b| v << 1 | b as u64),
+      };
+      let idx = self.map.index(value, u64::MAX >> (64 - width));
+      fields
+        .extend((0..field.len()).map(|i| (i == 0 && start != 0, value, idx)));
+    }
+    fields
+  }
+
+  /// The color to write the note of the `i`th annotation in. Notes without
+  /// a color of their own use the terminal's, since the theme's highlight
+  /// color is usually too dark to read as text.
+  fn note_color(&self, i: usize) -> TermColor {
+    match self.annotations[i].color {
+      Some(_) => self.annotation_colors[i],
+      None => TermColor::Reset,
     }
-    impl Colors {
-      fn term_color(&self, idx: usize) -> TermColor {
-        match self {
-          Colors::Quantized(cs) => TermColor::Index(cs[idx] + 16),
-          Colors::True(cs) => TermColor::Rgb(cs[idx]),
-        }
+  }
+
+  /// The number of the footnote for the `i`th annotation, if it has one.
+  fn footnote(&self, i: usize) -> Option<usize> {
+    self.footnotes.as_ref()?[i]
+  }
+
+  /// Lists the notes of the annotations on any of the regions that were
+  /// dumped, numbered as they were marked.
+  fn render_footnotes(&self, out: &mut dyn io::Write) -> io::Result<()> {
+    let shown = self.annotations.iter().enumerate();
+    let shown = shown.filter_map(|(i, a)| Some((i, self.footnote(i)?, a)));
+    for (i, number, a) in shown {
+      if number == 1 {
+        writeln!(out)?;
+      }
+      if self.esc {
+        self.note_color(i).fg(out)?;
       }
+      write!(out, "[{number}]")?;
+      if self.esc {
+        TermColor::Reset.fg(out)?;
+      }
+      let note = a.note.as_deref().unwrap_or_default();
+      writeln!(out, " {:#x}..{:#x}: {note}", a.range.start, a.range.end)?;
     }
+    Ok(())
+  }
 
-    let (colors, ascii_colors) = if self.use_truecolor {
-      (
-        Colors::True(color::make_gradient(&self.gradient, 256)),
-        self.ascii.as_ref().map(|ac| {
-          Colors::True(vec![
-            ac.unprintable,
-            ac.upper,
-            ac.lower,
-            ac.number,
-            ac.punct,
-          ])
-        }),
-      )
-    } else {
-      let quanta = color::XTERM256_PALETTE
-        .iter()
-        .map(|&x| Srgb::from_components(x).into_format::<f32>().into_color())
-        .collect::<Vec<_>>();
-      (
-        Colors::Quantized(color::make_quantized_gradient(
-          &self.gradient,
-          256,
-          &quanta,
-        )),
-        self.ascii.as_ref().map(|ac| {
-          Colors::Quantized(color::quantize_rgb(
-            [ac.unprintable, ac.upper, ac.lower, ac.number, ac.punct],
-            &quanta,
-          ))
+  /// Returns the notes to write beside the line starting `line_start` bytes
+  /// into the region: those on annotations that start on it, as well as any
+  /// already under way if it's the first line of the region. Each comes
+  /// with the index of its annotation.
+  fn notes(&self, line_start: u64) -> impl Iterator<Item = (usize, &str)> {
+    let start = self.region_start + line_start;
+    let line_len = (self.bytes_per_word * self.words_per_line) as u64;
+    let end = start + line_len;
+    self
+      .annotations
+      .iter()
+      .enumerate()
+      .filter_map(move |(i, a)| {
+        let note = a.note.as_deref()?;
+        let starts = (start..end).contains(&a.range.start);
+        let under_way =
+          line_start == 0 && a.range.start < start && start < a.range.end;
+        (starts || under_way).then_some((i, note))
+      })
+  }
+
+  /// Works out the sign and magnitude of the row label for `offset`.
+  fn label_value(&self, offset: u64) -> Option<(&'static str, u64)> {
+    let unit = match self.row_label_style {
+      RowLabelStyle::None => return None,
+      RowLabelStyle::Byte => 1,
+      RowLabelStyle::Word => self.bytes_per_word,
+      RowLabelStyle::Line => self.bytes_per_word * self.words_per_line,
+    } as u64;
+    let (sign, value) = match self.relative_to {
+      None => ("", offset),
+      Some(mark) if offset < mark => ("-", mark - offset),
+      Some(mark) => ("+", offset - mark),
+    };
+    Some((sign, value / unit))
+  }
+
+  /// Returns the prefix, radix, and digits that row labels are written
+  /// with.
+  fn label_radix(&self) -> (&'static str, u64, &'static [u8]) {
+    match self.row_label_base {
+      RowLabelBase::Hex => ("0x", 16, ALPHABET),
+      RowLabelBase::Decimal => ("", 10, ALPHABET),
+      RowLabelBase::Octal => ("0o", 8, ALPHABET),
+      RowLabelBase::Dump => {
+        let prefix = match self.log2_base {
+          1 => "0b",
+          3 => "0o",
+          4 => "0x",
+          _ => "",
+        };
+        (prefix, 1 << self.log2_base, self.alphabet)
+      }
+    }
+  }
+
+  /// Returns how many digits the row label for `offset` has.
+  fn label_digits(&self, offset: u64) -> usize {
+    let Some((_, mut value)) = self.label_value(offset) else {
+      return 0;
+    };
+    let (_, radix, _) = self.label_radix();
+    let mut digits = 1;
+    while value >= radix {
+      value /= radix;
+      digits += 1;
+    }
+    digits
+  }
+
+  /// Returns the offset to label the line `byte_idx` bytes into the region
+  /// with. The line is `into` bytes into `data_len` bytes that were
+  /// decompressed from `compressed`, if rows are labeled with that.
+  fn label_offset(
+    &self,
+    compressed: Option<&Range<u64>>,
+    data_len: usize,
+    into: u64,
+    byte_idx: u64,
+  ) -> u64 {
+    match (compressed, self.compressed_offsets) {
+      (Some(span), Some(offset)) => {
+        let scaled =
+          into as u128 * (span.end - span.start) as u128 / data_len as u128;
+        offset + span.start + scaled as u64
+      }
+      _ => self.display_offset_start + byte_idx,
+    }
+  }
+
+  /// Returns the row label for the line starting at `offset`.
+  fn label_text(&self, offset: u64) -> Option<String> {
+    let (sign, mut value) = self.label_value(offset)?;
+    let (prefix, radix, alphabet) = self.label_radix();
+    let mut digits = Vec::new();
+    loop {
+      digits.push(alphabet[(value % radix) as usize] as char);
+      value /= radix;
+      if value == 0 {
+        break;
+      }
+    }
+    let pad = self.label_width.saturating_sub(digits.len());
+    let mut label = format!("{sign}{prefix}");
+    label.extend(std::iter::repeat_n('0', pad));
+    label.extend(digits.iter().rev());
+    Some(label)
+  }
+
+  /// Writes the row label for the line starting at `offset`.
+  fn row_label(&self, w: &mut Vec<u8>, offset: u64) -> io::Result<()> {
+    if let Some(label) = self.label_text(offset) {
+      w.extend_from_slice(label.as_bytes());
+      w.extend_from_slice(b":  ");
+    }
+    Ok(())
+  }
+
+  /// Renders the next `len` bytes of `r`, on up to `jobs` threads, passing
+  /// them to `seen` in order as they're read. Returns how many there were.
+  fn region(
+    &self,
+    r: &mut Input,
+    len: u64,
+    jobs: usize,
+    seen: &mut dyn FnMut(&[u8]),
+    out: &mut dyn io::Write,
+  ) -> io::Result<u64> {
+    // Input is split into segments of whole lines, which are rendered on up
+    // to `jobs` threads at a time and written out in order.
+    let line_len = (self.bytes_per_word * self.words_per_line) as usize;
+    let segment_len = line_len * (SEGMENT_LEN / line_len).max(1);
+    let jobs = jobs.max(1);
+
+    // Along with each segment, we note which part of the compressed input it
+    // was decompressed from, if we're labeling rows with that.
+    let mut remaining = len;
+    let mut read = |r: &mut Input| -> io::Result<_> {
+      let start = r.compressed_pos();
+      let data = read_segment(r, segment_len, &mut remaining)?;
+      let span = self
+        .compressed_offsets
+        .and(start)
+        .zip(r.compressed_pos())
+        .map(|(start, end)| start..end);
+      Ok((data, span))
+    };
+
+    let mut first_byte = 0;
+    let mut next = read(r)?;
+    let mut done = false;
+    while !done {
+      // A segment is the last one if nothing follows it, so reading runs one
+      // segment ahead.
+      let mut batch = Vec::new();
+      while batch.len() < jobs && !done {
+        let following = match next.0.len() == segment_len {
+          true => read(r)?,
+          false => (Vec::new(), None),
+        };
+        done = following.0.is_empty();
+        let (data, span) = std::mem::replace(&mut next, following);
+        let len = data.len() as u64;
+        seen(&data);
+        batch.push((data, span, first_byte, done));
+        first_byte += len;
+      }
+
+      let outputs = match &batch[..] {
+        [(data, span, first, last)] => {
+          vec![self.lines(data, span.clone(), *first, *last)]
+        }
+        _ => thread::scope(|s| {
+          let handles = batch
+            .iter()
+            .map(|(data, span, first, last)| {
+              s.spawn(move || self.lines(data, span.clone(), *first, *last))
+            })
+            .collect::<Vec<_>>();
+          handles
+            .into_iter()
+            .map(|h| h.join().expect("render thread panicked"))
+            .collect()
         }),
-      )
+      };
+      for output in outputs {
+        out.write_all(&output?)?;
+      }
+    }
+    Ok(first_byte)
+  }
+
+  /// Renders the next `len` bytes of `r` as they arrive, waiting for more
+  /// whenever it runs dry, unless `r` can't `grow`. Whatever's left of the
+  /// last line is written once it's done.
+  ///
+  /// Whole lines are written out as soon as they're read. If `redraw` is set,
+  /// so is the partial line at the end of what's been read so far, which is
+  /// erased and drawn again when more bytes turn up. Bytes are passed to
+  /// `seen` as they're read. Returns how many were rendered.
+  fn follow(
+    &mut self,
+    r: &mut dyn io::Read,
+    len: u64,
+    grows: bool,
+    redraw: bool,
+    seen: &mut dyn FnMut(&[u8]),
+    out: &mut dyn io::Write,
+  ) -> io::Result<u64> {
+    let mut line_len = (self.bytes_per_word * self.words_per_line) as usize;
+
+    let mut remaining = len;
+    let mut first_byte = 0;
+    let mut pending = Vec::new();
+    let mut drawn = false;
+    let mut buf = vec![0; SEGMENT_LEN];
+    while remaining > 0 {
+      let want = buf
+        .len()
+        .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+      let n = match r.read(&mut buf[..want]) {
+        // Pipes and the like won't get any longer once they hit their end.
+        Ok(0) if !grows => break,
+        Ok(0) => {
+          if redraw && !drawn && !pending.is_empty() {
+            let mut line = self.lines(&pending, None, first_byte, true)?;
+            line.pop(); // Leave the cursor on the partial line.
+            out.write_all(&line)?;
+            drawn = true;
+          }
+          out.flush()?;
+          thread::sleep(FOLLOW_INTERVAL);
+          continue;
+        }
+        Ok(n) => n,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(e) => return Err(e),
+      };
+      remaining -= n as u64;
+      seen(&buf[..n]);
+      pending.extend_from_slice(&buf[..n]);
+
+      if drawn {
+        out.write_all(b"\r\x1b[K")?;
+        drawn = false;
+      }
+
+      // If the terminal has been resized, lines from here on are fitted to
+      // the new width, as though the region started here.
+      if let Some(width) = terminal_width().filter(|_| self.fit_width) {
+        let words_per_line = self.words_per_line;
+        self.fit(width);
+        if self.words_per_line != words_per_line {
+          self.display_offset_start += first_byte;
+          self.region_start += first_byte;
+          first_byte = 0;
+          line_len = (self.bytes_per_word * self.words_per_line) as usize;
+        }
+      }
+      let whole = pending.len() / line_len * line_len;
+      if whole > 0 {
+        out.write_all(&self.lines(
+          &pending[..whole],
+          None,
+          first_byte,
+          false,
+        )?)?;
+        first_byte += whole as u64;
+        pending.drain(..whole);
+      }
+    }
+    if !pending.is_empty() {
+      out.write_all(&self.lines(&pending, None, first_byte, true)?)?;
+    }
+    Ok(first_byte + pending.len() as u64)
+  }
+
+  /// Works out the glyphs of `word`, which starts at `at` in the input, and
+  /// how each one is colored. They're left in `buf.glyphs`.
+  fn layout(&self, word: &[u8], at: u64, buf: &mut WordBuf) {
+    // A word is drawn as a single number, so a little-endian word has its
+    // bytes reversed first. A partial word at the end is padded out with zero
+    // bytes to whole chunks.
+    let chunk_len = self.chunk_len as usize;
+    let padded = word.len().div_ceil(chunk_len) * chunk_len;
+    let display = &mut buf.display;
+    display.clear();
+    display.extend_from_slice(word);
+    display.resize(padded.clamp(word.len(), self.bytes_per_word as usize), 0);
+    if self.little_endian {
+      display.reverse();
+    }
+
+    // Formulas see each lane of the word as a number, read in the order the
+    // word is drawn in.
+    let lane_len = self.lane_len as usize;
+    let lane_max = u64::MAX >> (64 - 8 * lane_len);
+    buf.lanes.clear();
+    for (pos, lane) in display.chunks(lane_len).enumerate() {
+      let pos = pos as u64;
+      let x = lane.iter().fold(0, |x, &b| x << 8 | b as u64);
+      let style = self
+        .style_calc
+        .as_ref()
+        .map(|calc| color::Style::from_bits(calc.eval(x, pos)));
+
+      // Bytes in the same section all get the same color, and bytes outside
+      // of any are left uncolored.
+      let first = pos as usize * lane_len;
+      let at = at
+        + match self.little_endian {
+          true => display.len().saturating_sub(first + 1),
+          false => first,
+        } as u64;
+      let section = self.sections.as_ref().map(|sections| {
+        let class = sections.iter().rposition(|s| s.contains(&at));
+        let count = sections.len() as u64;
+        let idx =
+          class.and_then(|c| self.map.index(c as u64, count.max(2) - 1));
+        (class.map(|c| c as u64), idx)
+      });
+      let (key, idx) = section.unwrap_or_else(|| {
+        let color = self.byte_calc.eval(x, pos);
+        (Some(color), self.map.index(color, lane_max))
+      });
+      // Annotations are highlighted in their own colors, over any
+      // highlighting from --style.
+      let annotation =
+        self.annotations.iter().rposition(|a| a.range.contains(&at));
+      let highlight = match annotation {
+        Some(i) => Some(self.annotation_colors[i]),
+        None => style.filter(|s| s.highlight).map(|_| self.highlight_color),
+      };
+      buf.lanes.push(Lane {
+        pos,
+        key,
+        idx,
+        style,
+        highlight,
+        in_section: section.is_some(),
+      });
+    }
+
+    let fields = self
+      .bit_fields
+      .as_ref()
+      .map(|widths| self.word_fields(widths, display));
+
+    // Words that aren't a whole number of glyphs wide are padded with zero
+    // bits at the top.
+    let base = 1u64 << self.log2_base;
+    let log2_base = self.log2_base as usize;
+    let bits = 8 * display.len();
+    let glyphs = bits.div_ceil(log2_base);
+    let lead = glyphs * log2_base - bits;
+    let (mut acc, mut held) = (0u32, lead);
+    let mut bytes = display.iter();
+    buf.glyphs.clear();
+    for i in 0..glyphs {
+      while held < log2_base {
+        let mut byte = bytes.next().copied().unwrap_or(0);
+        // Bit order only applies to base 2.
+        if self.bit_order == BitOrder::Lsb {
+          byte = byte.reverse_bits();
+        }
+        acc = acc << 8 | byte as u32;
+        held += 8;
+      }
+      held -= log2_base;
+      let digit = (acc >> held) as u64 & (base - 1);
+      acc &= (1 << held) - 1;
+
+      let bit = (i * log2_base).saturating_sub(lead);
+      let lane = buf.lanes[bit / (8 * lane_len)];
+
+      // Sections take precedence over any other coloring.
+      let field = fields.as_ref().and_then(|f| f.get(i));
+      let (key, idx) = match field {
+        Some(&(_, _, idx)) if !lane.in_section => (idx.map(|i| i as u64), idx),
+        _ if self.color_single_glyphs && !lane.in_section => {
+          let color = self.glyph_calc.eval(digit, lane.pos);
+          (Some(color), self.map.index(color, base - 1))
+        }
+        _ => (lane.key, lane.idx),
+      };
+      buf.glyphs.push(Glyph {
+        digit: digit as u8,
+        field_start: matches!(field, Some((true, _, _))),
+        key,
+        value: match field {
+          Some(&(_, value, _)) if !lane.in_section => Some(value),
+          _ => key,
+        },
+        idx,
+        style: lane.style,
+        highlight: lane.highlight,
+      });
+    }
+  }
+
+  /// Returns the foreground and background `glyph` is drawn in, or `None`
+  /// for the terminal's own.
+  fn glyph_colors(
+    &self,
+    glyph: &Glyph,
+  ) -> (Option<TermColor>, Option<TermColor>) {
+    let color = glyph.idx.map(|i| self.colors.term_color(i));
+    match (glyph.highlight, self.background) {
+      (Some(highlight), _) => (color, Some(highlight)),
+      (None, false) => (color, None),
+      (None, true) => (glyph.idx.map(|i| self.colors.contrasting(i)), color),
+    }
+  }
+
+  /// Renders `data` like [`Renderer::lines`] does, but as a JSON object for
+  /// each line, which gives its bytes and how each glyph is colored.
+  fn json_lines(
+    &self,
+    data: &[u8],
+    compressed: Option<Range<u64>>,
+    first_byte: u64,
+  ) -> io::Result<Vec<u8>> {
+    let hex = |color: Option<TermColor>| {
+      let c = color?.rgb()?;
+      Some(format!("#{:02x}{:02x}{:02x}", c.red, c.green, c.blue))
     };
 
+    let line_len = (self.bytes_per_word * self.words_per_line) as usize;
+    let mut buf = WordBuf::default();
+    let mut w = Vec::new();
+    for (n, line) in data.chunks(line_len).enumerate() {
+      let into = (n * line_len) as u64;
+      let line_start = first_byte + into;
+
+      let mut words = Vec::new();
+      let word_len = self.bytes_per_word as usize;
+      for (i, word) in line.chunks(word_len).enumerate() {
+        let at = self.region_start + line_start + (i * word_len) as u64;
+        self.layout(word, at, &mut buf);
+        let mut text = String::new();
+        let mut glyphs = Vec::new();
+        for glyph in &buf.glyphs {
+          if glyph.field_start {
+            text.push('\'');
+          }
+          let digit = self.alphabet[glyph.digit as usize] as char;
+          text.push(digit);
+
+          let (fg, bg) = self.glyph_colors(glyph);
+          let mut object = json!({
+            "glyph": digit.to_string(),
+            "value": glyph.value,
+            "fg": hex(fg),
+            "bg": hex(bg),
+          });
+          if let Some(style) = glyph.style {
+            let flags = [
+              ("bold", style.bold),
+              ("dim", style.dim),
+              ("underline", style.underline),
+            ];
+            object["style"] = flags
+              .iter()
+              .filter(|(_, on)| *on)
+              .map(|(name, _)| *name)
+              .collect();
+          }
+          glyphs.push(object);
+        }
+        words.push(json!({"text": text, "glyphs": glyphs}));
+      }
+
+      let offset =
+        self.label_offset(compressed.as_ref(), data.len(), into, line_start);
+      let mut object = json!({
+        "offset": self.region_start + line_start,
+        "label": self.label_text(offset),
+        "bytes": line,
+        "words": words,
+      });
+
+      // The ASCII render is given as runs of characters of the same color.
+      if let Some(ascii_colors) = &self.ascii_colors {
+        let mut runs = Vec::<(usize, String)>::new();
+        for &b in line {
+          let class = ascii_class(b);
+          let c = match b {
+            0x20..0x7f => b as char,
+            _ => '·',
+          };
+          match runs.last_mut() {
+            Some((last, text)) if *last == class => text.push(c),
+            _ => runs.push((class, c.to_string())),
+          }
+        }
+        object["ascii"] = runs
+          .into_iter()
+          .map(|(class, text)| {
+            let fg = hex(Some(ascii_colors.term_color(class)));
+            json!({"text": text, "fg": fg})
+          })
+          .collect();
+      }
+
+      let notes = self
+        .notes(line_start)
+        .map(|(i, note)| {
+          let fg = hex(Some(self.note_color(i)));
+          json!({"note": note, "fg": fg})
+        })
+        .collect::<Vec<_>>();
+      if !notes.is_empty() {
+        object["notes"] = notes.into();
+      }
+
+      // Objects in an array are separated by commas.
+      let first = !self.json_started && line_start == 0;
+      if self.format == Format::Json && !first {
+        w.push(b',');
+      }
+      serde_json::to_writer(&mut w, &object)?;
+      w.push(b'\n');
+    }
+    Ok(w)
+  }
+
+  /// Renders `data`, which starts `first_byte` bytes into the region being
+  /// dumped. If it was decompressed from the `compressed` part
+  /// of the input, rows are labeled with offsets spread evenly across it.
+  ///
+  /// Unless this is the `last` part of the input, `data` must be made of
+  /// whole lines, and the output ends with the newline after the last of
+  /// them.
+  fn lines(
+    &self,
+    data: &[u8],
+    compressed: Option<Range<u64>>,
+    first_byte: u64,
+    last: bool,
+  ) -> io::Result<Vec<u8>> {
+    if self.format != Format::Text {
+      return self.json_lines(data, compressed, first_byte);
+    }
+
+    let esc = self.esc;
+    let colors = &self.colors;
+    let background = self.background;
+    let styled = self.style_calc.is_some();
+    let annotated = esc && !self.annotations.is_empty();
+
     let bytes_per_line = self.words_per_line * self.bytes_per_word;
+    // The gutter of a partial last line has always been padded as though it
+    // had a column per chunk, rather than per byte.
+    let gutter_len = bytes_per_line / self.chunk_len;
     let render_ascii =
-      |w: &mut dyn io::Write, ascii_buf: &mut Vec<u8>| -> io::Result<()> {
+      |w: &mut Vec<u8>, ascii_buf: &mut Vec<u8>| -> io::Result<()> {
         let mut last_color = None;
 
-        while ascii_buf.len() < bytes_per_line as usize {
+        while ascii_buf.len() < gutter_len as usize {
           ascii_buf.push(0);
         }
 
-        if let Some(ascii_colors) = &ascii_colors {
-          TermColor::Reset.fg(w)?;
-          write!(w, "  |")?;
+        if let Some(ascii_colors) = &self.ascii_colors {
+          if esc {
+            TermColor::Reset.fg(w)?;
+          }
+          w.extend_from_slice(b"  ");
+          if let Some(color) = self.separator_color {
+            color.fg(w)?;
+          }
+          w.push(b'|');
           for &b in &*ascii_buf {
-            let color = if b.is_ascii_uppercase() {
-              1
-            } else if b.is_ascii_lowercase() {
-              2
-            } else if b.is_ascii_digit() {
-              3
-            } else if b.is_ascii_punctuation() {
-              4
-            } else {
-              0
-            };
+            let color = ascii_class(b);
 
-            if last_color != Some(color) {
+            if esc && last_color != Some(color) {
               last_color = Some(color);
               ascii_colors.term_color(color).fg(w)?;
             }
 
             if b > 0x1f && b < 0x7f {
-              write!(w, "{}", b as char)?;
+              w.push(b);
             } else {
-              write!(w, "·")?;
+              w.extend_from_slice("·".as_bytes());
             }
           }
-          TermColor::Reset.fg(w)?;
-          write!(w, "|")?;
+          match self.separator_color {
+            Some(color) => color.fg(w)?,
+            None if esc => TermColor::Reset.fg(w)?,
+            None => {}
+          }
+          w.push(b'|');
           ascii_buf.clear();
         }
         Ok(())
       };
 
-    let file_offset = std::cell::Cell::new(self.display_offset_start);
-    let mut byte_idx = 0;
-    let mut word_idx = 0;
+    // Notes go in the margin after the gutter.
+    let render_notes = |w: &mut Vec<u8>, line_start: u64| -> io::Result<()> {
+      let mut notes = self.notes(line_start).peekable();
+      if notes.peek().is_none() {
+        return Ok(());
+      }
+      w.extend_from_slice(b"  ");
+      for (n, (i, note)) in notes.enumerate() {
+        if esc {
+          self.note_color(i).fg(w)?;
+        }
+        match (self.footnote(i), n) {
+          (Some(number), 0) => write!(w, "[{number}]")?,
+          (Some(number), _) => write!(w, " [{number}]")?,
+          (None, 0) => w.extend_from_slice(b"<- "),
+          (None, _) => w.extend_from_slice(b"; "),
+        }
+        // Without highlighting, the note has to say which bytes it's on.
+        if self.footnotes.is_none() {
+          if !esc {
+            let range = &self.annotations[i].range;
+            write!(w, "{:#x}..{:#x} ", range.start, range.end)?;
+          }
+          w.extend_from_slice(note.as_bytes());
+        }
+      }
+      if esc {
+        TermColor::Reset.fg(w)?;
+      }
+      Ok(())
+    };
+
+    // Checksums of each line go after the gutter, in the row label color.
+    let render_sums = |w: &mut Vec<u8>, line: Range<u64>| -> io::Result<()> {
+      let line = &data
+        [(line.start - first_byte) as usize..(line.end - first_byte) as usize];
+      if let Some(color) = self.row_label_color {
+        color.fg(w)?;
+      }
+      for algorithm in &self.line_checksums {
+        write!(w, "  {}", algorithm.sum(line))?;
+      }
+      if self.row_label_color.is_some() {
+        TermColor::Reset.fg(w)?;
+      }
+      Ok(())
+    };
+    let summed = !self.line_checksums.is_empty();
+
+    let mut byte_idx = first_byte;
     let mut last_byte = None;
     let mut glyphs_in_line = 0;
     let mut ascii_buf = Vec::<u8>::new();
-    let mut calc_stack = Vec::<u64>::new();
 
-    let mut draw = |buf: [u8; 8],
-                    buf_len: usize,
-                    w: &mut dyn io::Write,
-                    ascii_buf: &mut Vec<u8>|
+    let mut last_style = None;
+    let mut last_highlight = None;
+    let mut line_start = first_byte;
+
+    // Sets the color for the glyphs that follow, either as their foreground
+    // or as their background. Highlighted glyphs keep their own color as the
+    // foreground, on top of the highlight color.
+    let paint = |w: &mut Vec<u8>,
+                 idx: Option<usize>,
+                 highlight: Option<TermColor>,
+                 was_highlighted: bool|
      -> io::Result<()> {
-      let mut bits = u64::from_le_bytes(buf);
-
-      if byte_idx % self.bytes_per_word == 0 {
-        if word_idx % self.words_per_line == 0 {
-          if byte_idx != 0 {
-            render_ascii(w, ascii_buf)?;
-            write!(w, "\n")?;
-            glyphs_in_line = 0;
+      if !esc {
+        return Ok(());
+      }
+      if let Some(highlight) = highlight {
+        highlight.bg(w)?;
+        return colors.term_color_or_reset(idx).fg(w);
+      }
+      match (background, idx) {
+        (false, Some(idx)) => {
+          if was_highlighted {
+            TermColor::Reset.bg(w)?;
           }
-          TermColor::Reset.fg(w)?;
-          last_byte = None;
-          match self.row_label_style {
-            RowLabelStyle::None => {}
-            RowLabelStyle::Byte => write!(w, "0x{:08x}:  ", file_offset.get())?,
-            RowLabelStyle::Word => write!(
-              w,
-              "0x{:08x}:  ",
-              file_offset.get() / ((chunk_len * self.bytes_per_word) as u64)
-            )?,
-            RowLabelStyle::Line => write!(
-              w,
-              "0x{:08x}:  ",
-              file_offset.get()
-                / ((chunk_len * self.bytes_per_word * self.words_per_line)
-                  as u64)
-            )?,
+          w.extend_from_slice(&self.fg_escapes[idx]);
+          Ok(())
+        }
+        (false, None) => {
+          if was_highlighted {
+            TermColor::Reset.bg(w)?;
           }
-        } else if byte_idx != 0 {
-          write!(w, " ")?;
-          glyphs_in_line += 1;
+          TermColor::Reset.fg(w)
+        }
+        (true, Some(idx)) => {
+          colors.term_color(idx).bg(w)?;
+          colors.contrasting(idx).fg(w)
+        }
+        (true, None) => {
+          TermColor::Reset.bg(w)?;
+          TermColor::Reset.fg(w)
         }
-        word_idx += 1;
       }
+    };
 
-      if !self.color_single_glyphs {
-        let color_byte =
-          self.calc.execute(bits, chunk_len * 8, &mut calc_stack);
-        if last_byte != Some(color_byte) {
-          last_byte = Some(color_byte);
-          let max_byte = ((1u64 << (chunk_len * 8)) - 1) as f64;
-          let idx = 255.0 * (color_byte as f64 / max_byte);
-          colors.term_color(idx as usize).fg(w)?;
-        }
+    // Backgrounds and text attributes are cleared before separators, so that
+    // they don't bleed into them.
+    let clear = |w: &mut Vec<u8>| -> io::Result<()> {
+      if background || styled || annotated {
+        TermColor::Reset.bg(w)?;
+      }
+      if styled {
+        color::Style::default().apply(w)?;
       }
+      Ok(())
+    };
 
-      for _ in 0..glyphs_per_byte {
-        let glyph = bits >> (chunk_len * 8 - self.log2_base) & (base - 1);
-        bits <<= self.log2_base;
+    let mut buf = WordBuf::default();
 
-        if self.color_single_glyphs {
-          let color_byte =
-            self.calc.execute(glyph, self.log2_base, &mut calc_stack);
-          if last_byte != Some(color_byte) {
-            last_byte = Some(color_byte);
-            let idx = 255.0 * (color_byte as f64 / (base as f64 - 1.0));
-            colors.term_color(idx as usize).fg(w)?;
+    let mut draw = |word: &[u8],
+                    w: &mut Vec<u8>,
+                    ascii_buf: &mut Vec<u8>|
+     -> io::Result<()> {
+      let word_idx = byte_idx / self.bytes_per_word as u64;
+      if byte_idx != first_byte && (background || styled || annotated) {
+        clear(w)?;
+        last_byte = None;
+        last_style = None;
+        last_highlight = None;
+      }
+      if word_idx.is_multiple_of(self.words_per_line as u64) {
+        if byte_idx != first_byte {
+          render_ascii(w, ascii_buf)?;
+          if summed {
+            render_sums(w, line_start..byte_idx)?;
           }
+          render_notes(w, line_start)?;
+          w.push(b'\n');
+          glyphs_in_line = 0;
+        }
+        line_start = byte_idx;
+        match self.row_label_color {
+          Some(color) => color.fg(w)?,
+          None if esc => TermColor::Reset.fg(w)?,
+          None => {}
         }
+        last_byte = None;
+        let file_offset = self.label_offset(
+          compressed.as_ref(),
+          data.len(),
+          byte_idx - first_byte,
+          byte_idx,
+        );
+        self.row_label(w, file_offset)?;
+      } else if byte_idx != first_byte {
+        write!(w, " ")?;
+        glyphs_in_line += 1;
+      }
 
-        // Code in main() stops -u from being mixed with base64.
-        let alphabet = if self.uppercase {
-          ALPHABET_UPPER
-        } else {
-          ALPHABET
-        };
+      self.layout(word, self.region_start + byte_idx, &mut buf);
+      for glyph in &buf.glyphs {
+        if glyph.field_start {
+          clear(w)?;
+          match self.separator_color {
+            Some(color) => color.fg(w)?,
+            None if esc => TermColor::Reset.fg(w)?,
+            None => {}
+          }
+          w.push(b'\'');
+          glyphs_in_line += 1;
+          last_byte = None;
+          last_style = None;
+          last_highlight = None;
+        }
+
+        if last_byte != Some(glyph.key) || last_highlight != glyph.highlight {
+          last_byte = Some(glyph.key);
+          paint(w, glyph.idx, glyph.highlight, last_highlight.is_some())?;
+          last_highlight = glyph.highlight;
+        }
+
+        if let Some(style) = glyph.style {
+          if last_style != Some(style) {
+            last_style = Some(style);
+            style.apply(w)?;
+          }
+        }
 
-        write!(w, "{}", alphabet[glyph as usize] as char)?;
+        w.push(self.alphabet[glyph.digit as usize]);
         glyphs_in_line += 1;
       }
 
-      if self.ascii.is_some() {
-        ascii_buf.extend(&buf[..buf_len]);
+      // The ASCII render is always in file order.
+      if self.ascii_colors.is_some() {
+        ascii_buf.extend_from_slice(word);
       }
-
-      byte_idx += 1;
+      byte_idx += word.len() as u64;
       Ok(())
     };
 
-    let mut buf = [0; 8];
-    let mut octets_count = 0;
-    for octet in self.r.bytes() {
-      let octet = octet?;
-      buf[octets_count] = octet;
-      octets_count += 1;
-      file_offset.set(file_offset.get() + 1);
-      if octets_count != chunk_len as usize {
-        continue;
-      }
+    let mut w = Vec::with_capacity(data.len() * 8);
+    for word in data.chunks(self.bytes_per_word as usize) {
+      draw(word, &mut w, &mut ascii_buf)?;
+    }
+    clear(&mut w)?;
 
-      if self.limit == 0 {
-        break;
+    if !last {
+      render_ascii(&mut w, &mut ascii_buf)?;
+      if summed {
+        render_sums(&mut w, line_start..byte_idx)?;
       }
-      self.limit -= 1;
+      render_notes(&mut w, line_start)?;
+      w.push(b'\n');
+      return Ok(w);
+    }
+
+    // Without a gutter, the last line has always been padded unless it's
+    // also the first, or unless it has notes or checksums to line up.
+    let noted = !data.is_empty() && self.notes(line_start).next().is_some();
+    let summed = summed && !data.is_empty();
+    let pad = match self.ascii_colors {
+      Some(_) => !ascii_buf.is_empty(),
+      None => byte_idx > bytes_per_line as u64 || noted || summed,
+    };
+    if pad {
+      let line_len = self.words_per_line
+        * (self.glyphs_per_word + self.seps_per_word)
+        + (self.words_per_line - 1);
+      let pad = (line_len as usize).saturating_sub(glyphs_in_line);
+      w.extend(std::iter::repeat_n(b' ', pad));
+      render_ascii(&mut w, &mut ascii_buf)?;
+    }
+    if summed {
+      render_sums(&mut w, line_start..byte_idx)?;
+    }
+    if noted {
+      render_notes(&mut w, line_start)?;
+    }
+
+    if esc {
+      TermColor::Reset.fg(&mut w)?;
+    }
+    w.push(b'\n');
+    Ok(w)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-      draw(buf, octets_count, &mut self.w, &mut ascii_buf)?;
-      octets_count = 0;
-      buf.fill(0);
+  /// Builds an uncolored renderer for words of `bytes_per_word` bytes in
+  /// base `2^log2_base`, with any other options set by `f`.
+  fn renderer(
+    log2_base: u32,
+    bytes_per_word: u32,
+    f: impl FnOnce(&mut RenderOpts),
+  ) -> Renderer {
+    let gray = Srgb::new(128, 128, 128);
+    let mut opts = RenderOpts {
+      log2_base,
+      bytes_per_word,
+      words_per_line: 1,
+      fit_width: false,
+      little_endian: false,
+      bit_fields: None,
+      bit_offset: 0,
+      bit_order: BitOrder::Msb,
+      lanes: None,
+      display_offset_start: 0,
+      regions: Vec::new(),
+      header: None,
+      format: Format::Text,
+
+      colors: ColorMap::Classes(vec![gray]),
+      gradient_space: Default::default(),
+      depth: Depth::None,
+      background: false,
+      legend: false,
+      color_single_glyphs: false,
+      ascii: Some(AsciiOpts {
+        unprintable: gray,
+        upper: gray,
+        lower: gray,
+        number: gray,
+        punct: gray,
+      }),
+      accents: Accents {
+        row_label: None,
+        separator: None,
+        highlight: gray,
+        header: gray,
+      },
+      uppercase: false,
+
+      row_label_style: RowLabelStyle::Byte,
+      row_label_base: RowLabelBase::Hex,
+      relative_to: None,
+      calc: Default::default(),
+      style: None,
+      calc_strategy: Default::default(),
+
+      annotations: Vec::new(),
+      footnotes: false,
+      checksums: Vec::new(),
+      line_checksums: false,
+
+      jobs: 1,
+      sections: None,
+      compressed_offsets: false,
+      follow: false,
+      redraw: false,
+
+      r: &mut Input::memory(Vec::new()),
+      w: &mut io::sink(),
+    };
+    f(&mut opts);
+    Renderer::new(&opts).unwrap()
+  }
+
+  /// How many columns the first full line drawn by `r` takes up.
+  fn line_width(r: &Renderer) -> usize {
+    let data = vec![b'A'; (r.bytes_per_word * r.words_per_line) as usize];
+    let out = r.lines(&data, None, 0, false).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    out.lines().next().unwrap().chars().count()
+  }
+
+  /// Fitting picks the most words that fit, in every base and with or
+  /// without the things drawn beside them.
+  #[test]
+  fn fit() {
+    let layouts = [(4, 4), (4, 1), (1, 1), (3, 3), (3, 6), (5, 5), (6, 6)];
+    for (log2_base, bytes_per_word) in layouts {
+      for extras in 0..4 {
+        let mut r = renderer(log2_base, bytes_per_word, |opts| {
+          if extras & 1 != 0 {
+            opts.ascii = None;
+          }
+          if extras & 2 != 0 {
+            opts.checksums = vec![checksum::Algorithm::Crc32];
+            opts.line_checksums = true;
+          }
+        });
+        for width in [30, 79, 80, 81, 120, 200] {
+          r.fit(width);
+          let words = r.words_per_line;
+          let case = format!(
+            "{bytes_per_word}-byte words in base {}, extras {extras}, \
+             width {width}",
+            1 << log2_base,
+          );
+          assert!(words == 1 || line_width(&r) <= width, "{case}");
+          r.words_per_line += 1;
+          assert!(line_width(&r) > width, "{case}: {words} words");
+        }
+      }
     }
+  }
 
-    if octets_count != 0 {
-      draw(buf, octets_count, &mut self.w, &mut ascii_buf)?;
+  /// A label and gutter that leave no room for a whole word still get one.
+  #[test]
+  fn fit_no_room() {
+    // A label, one four-byte word, and its ASCII take 29 columns, and each
+    // word after that 13 more.
+    let mut r = renderer(4, 4, |_| {});
+    for width in [0, 1, 13, 28, 29, 41] {
+      r.fit(width);
+      assert_eq!(r.words_per_line, 1, "width {width}");
     }
+    assert_eq!(line_width(&r), 29);
+    r.fit(42);
+    assert_eq!(r.words_per_line, 2);
+    assert_eq!(line_width(&r), 42);
+  }
 
-    if !ascii_buf.is_empty() {
-      let line_len =
-        self.words_per_line * self.bytes_per_word * glyphs_per_byte
-          + (self.words_per_line - 1);
-      for _ in glyphs_in_line..line_len as usize {
-        write!(self.w, " ")?;
+  /// The same, with bit fields, whose separators take up room too.
+  #[test]
+  fn fit_bit_fields() {
+    for fields in [vec![3, 5], vec![1], vec![4, 4, 8], vec![7]] {
+      let mut r = renderer(1, 2, |opts| opts.bit_fields = Some(fields.clone()));
+      for width in [40, 80, 120] {
+        r.fit(width);
+        let words = r.words_per_line;
+        assert!(words == 1 || line_width(&r) <= width, "{fields:?}");
+        r.words_per_line += 1;
+        assert!(line_width(&r) > width, "{fields:?}: {words} words");
       }
-      render_ascii(&mut self.w, &mut ascii_buf)?;
     }
+  }
+
+  #[test]
+  fn labels() {
+    let label = |r: &Renderer, offset| r.label_text(offset).unwrap();
+    let r = renderer(4, 4, |_| {});
+    assert_eq!(label(&r, 0x1234), "0x00001234");
+    assert_eq!(label(&r, u64::MAX), "0xffffffffffffffff");
+
+    let r = renderer(4, 4, |opts| opts.row_label_base = RowLabelBase::Decimal);
+    assert_eq!(label(&r, 0x1234), "00004660");
+    let r = renderer(4, 4, |opts| opts.row_label_base = RowLabelBase::Octal);
+    assert_eq!(label(&r, 8), "0o00000010");
+    // Labels in the same base as the dump use its alphabet.
+    let r = renderer(6, 6, |opts| opts.row_label_base = RowLabelBase::Dump);
+    assert_eq!(label(&r, 64 * 63 + 62), "000000/+");
+    let r = renderer(1, 1, |opts| opts.row_label_base = RowLabelBase::Dump);
+    assert_eq!(label(&r, 5), "0b00000101");
+
+    // Relative labels count both ways from their mark.
+    let r = renderer(4, 4, |opts| opts.relative_to = Some(0x100));
+    assert_eq!(label(&r, 0x100), "+0x00000000");
+    assert_eq!(label(&r, 0x110), "+0x00000010");
+    assert_eq!(label(&r, 0xf0), "-0x00000010");
+
+    // Word and line labels count words and lines.
+    let r = renderer(4, 4, |opts| {
+      opts.row_label_style = RowLabelStyle::Word;
+      opts.relative_to = Some(0x10);
+    });
+    assert_eq!(label(&r, 0x30), "+0x00000008");
+    let r = renderer(4, 4, |opts| {
+      opts.row_label_style = RowLabelStyle::Line;
+      opts.words_per_line = 4;
+    });
+    assert_eq!(label(&r, 0x30), "0x00000003");
+    let r = renderer(4, 4, |opts| opts.row_label_style = RowLabelStyle::None);
+    assert_eq!(r.label_text(0x30), None);
+  }
+
+  // Binary literals are grouped by field.
+  #[test]
+  #[allow(clippy::unusual_byte_groupings)]
+  fn word_fields() {
+    let fields = |widths: &[u32], offset, order, word: &[u8]| {
+      let r = renderer(1, word.len() as u32, |opts| {
+        opts.bit_fields = Some(widths.to_vec());
+        opts.bit_offset = offset;
+        opts.bit_order = order;
+      });
+      let fields = r.word_fields(widths, word);
+      assert_eq!(fields.len(), 8 * word.len());
+      // Each field's start, and its value.
+      let starts = fields.iter().enumerate().filter(|(i, f)| *i == 0 || f.0);
+      starts.map(|(i, f)| (i, f.1)).collect::<Vec<_>>()
+    };
 
-    TermColor::Reset.fg(self.w)?;
-    write!(self.w, "\n")
+    let msb = BitOrder::Msb;
+    assert_eq!(
+      fields(&[3, 5], 0, msb, &[0b101_11001]),
+      [(0, 0b101), (3, 0b11001)],
+    );
+    // Widths repeat across the word, and the last field is cut short at the
+    // end of it.
+    assert_eq!(
+      fields(&[3, 5], 0, msb, &[0b101_11001, 0b011_10000]),
+      [(0, 0b101), (3, 0b11001), (8, 0b011), (11, 0b10000)],
+    );
+    assert_eq!(
+      fields(&[5], 0, msb, &[0xff, 0xff]),
+      [(0, 31), (5, 31), (10, 31), (15, 1)],
+    );
+    // The offset is a field of its own.
+    assert_eq!(
+      fields(&[4], 2, msb, &[0b10_1100_11]),
+      [(0, 0b10), (2, 0b1100), (6, 0b11)],
+    );
+    // Fields wider than the word take all of it.
+    assert_eq!(fields(&[12], 0, msb, &[0xa5]), [(0, 0xa5)]);
+    // LSB first, the first bit drawn is the least significant.
+    assert_eq!(
+      fields(&[3, 5], 0, BitOrder::Lsb, &[0b11001_101]),
+      [(0, 0b101), (3, 0b11001)],
+    );
   }
 }
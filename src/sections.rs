@@ -0,0 +1,64 @@
+// Copyright 2022 Miguel Young de la Sota
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sections of executables and object files.
+
+use std::ops::Range;
+
+use object::Object as _;
+use object::ObjectSection as _;
+
+/// A section of an ELF, PE, or Mach-O file that takes up space in the file.
+#[derive(Clone, Debug)]
+pub struct Section {
+  pub name: String,
+  /// Where the section's contents are in the file.
+  pub range: Range<u64>,
+  /// Where the section is loaded in memory.
+  pub addr: u64,
+}
+
+/// Parses the section headers of `data`, returning the sections that have
+/// contents in the file, ordered by where they are in it. Only the headers
+/// are read, so `data` can be a [`object::ReadCache`] over a large file.
+pub fn parse<'data>(
+  data: impl object::ReadRef<'data>,
+) -> Result<Vec<Section>, String> {
+  let file = object::File::parse(data)
+    .map_err(|e| format!("not an ELF, PE, or Mach-O file: {e}"))?;
+  let mut sections = file
+    .sections()
+    .filter_map(|section| {
+      let (start, len) = section.file_range()?;
+      if len == 0 {
+        return None;
+      }
+      let name = section.name().unwrap_or("?");
+      // Mach-O sections are only unique within their segment.
+      let name = match section.segment_name() {
+        Ok(Some(segment)) if !name.starts_with('.') => {
+          format!("{segment},{name}")
+        }
+        _ => name.to_string(),
+      };
+      Some(Section {
+        name,
+        range: start..start + len,
+        addr: section.address(),
+      })
+    })
+    .collect::<Vec<_>>();
+  sections.sort_by_key(|s| (s.range.start, s.range.end));
+  Ok(sections)
+}
@@ -0,0 +1,307 @@
+// Copyright 2022 Miguel Young de la Sota
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Color themes.
+//!
+//! A theme is a small text file of `key = value` lines, where the values are
+//! written just like the corresponding command-line options:
+//!
+//! ```text
+//! # Lines starting with a hash are comments.
+//! gradient = #268bd2,#2aa198,#859900,#b58900,#cb4b16,#dc322f
+//! gradient-space = lab
+//! ascii = #268bd2,#2aa198,#b58900,#d33682,#586e75
+//! row-label = #586e75
+//! separator = #586e75
+//! highlight = #073642
+//! header = #268bd2
+//! ```
+//!
+//! Any keys that are left out are taken from the default theme.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A set of colors for every part of a dump. Values are kept as they were
+/// written, in the syntax of the matching command-line option.
+#[derive(Clone, Debug)]
+pub struct Theme {
+  pub name: String,
+  /// Byte colors, as for `-z`.
+  pub gradient: String,
+  /// As for `--gradient-space`.
+  pub gradient_space: String,
+  /// ASCII gutter colors, as for `-y`.
+  pub ascii: String,
+  /// The color of row labels; uncolored if missing.
+  pub row_label: Option<String>,
+  /// The color of the bars around the ASCII gutter; uncolored if missing.
+  pub separator: Option<String>,
+  /// The background of bytes highlighted by `--style`.
+  pub highlight: String,
+  /// The color of the header above each input, when there are several.
+  pub header: String,
+}
+
+/// Themes that ship with `0x`.
+const BUILTIN: &[(&str, &str)] = &[
+  (
+    "default",
+    "
+    gradient = red,orangered,orange,gold,yellow,lightyellow
+    gradient-space = hsv
+    ascii = mariana
+    highlight = #3a3a3a
+    header = #5fb3b3
+    ",
+  ),
+  (
+    "light",
+    "
+    gradient = darkred,firebrick,orangered,darkorange,darkgoldenrod
+    ascii = #8959a8,#3e999f,#c18401,#c82829,slategray
+    highlight = #dadada
+    header = #3e999f
+    ",
+  ),
+  (
+    "colorblind",
+    "
+    gradient = cividis
+    ascii = okabe-ito
+    highlight = #3a3a3a
+    header = #56b4e9
+    ",
+  ),
+  (
+    "solarized-dark",
+    "
+    gradient = #268bd2,#2aa198,#859900,#b58900,#cb4b16,#dc322f
+    gradient-space = lab
+    ascii = #268bd2,#2aa198,#b58900,#d33682,#586e75
+    row-label = #586e75
+    separator = #586e75
+    highlight = #073642
+    header = #268bd2
+    ",
+  ),
+  (
+    "solarized-light",
+    "
+    gradient = #268bd2,#2aa198,#859900,#b58900,#cb4b16,#dc322f
+    gradient-space = lab
+    ascii = #268bd2,#2aa198,#b58900,#d33682,#93a1a1
+    row-label = #93a1a1
+    separator = #93a1a1
+    highlight = #eee8d5
+    header = #268bd2
+    ",
+  ),
+  (
+    "gruvbox-dark",
+    "
+    gradient = #83a598,#8ec07c,#b8bb26,#fabd2f,#fe8019,#fb4934
+    gradient-space = lab
+    ascii = #83a598,#8ec07c,#fabd2f,#d3869b,#928374
+    row-label = #928374
+    separator = #665c54
+    highlight = #504945
+    header = #83a598
+    ",
+  ),
+  (
+    "gruvbox-light",
+    "
+    gradient = #076678,#427b58,#79740e,#b57614,#af3a03,#9d0006
+    gradient-space = lab
+    ascii = #076678,#427b58,#b57614,#8f3f71,#928374
+    row-label = #928374
+    separator = #bdae93
+    highlight = #d5c4a1
+    header = #076678
+    ",
+  ),
+  (
+    "dracula",
+    "
+    gradient = #bd93f9,#ff79c6,#ff5555,#ffb86c,#f1fa8c
+    gradient-space = lab
+    ascii = #bd93f9,#8be9fd,#50fa7b,#ff79c6,#6272a4
+    row-label = #6272a4
+    separator = #6272a4
+    highlight = #44475a
+    header = #bd93f9
+    ",
+  ),
+  (
+    "nord",
+    "
+    gradient = #5e81ac,#81a1c1,#88c0d0,#8fbcbb,#a3be8c,#ebcb8b
+    gradient-space = lab
+    ascii = #8fbcbb,#88c0d0,#b48ead,#ebcb8b,#4c566a
+    row-label = #4c566a
+    separator = #4c566a
+    highlight = #434c5e
+    header = #88c0d0
+    ",
+  ),
+  (
+    "one-dark",
+    "
+    gradient = #61afef,#56b6c2,#98c379,#e5c07b,#d19a66,#e06c75
+    gradient-space = lab
+    ascii = #61afef,#98c379,#d19a66,#c678dd,#5c6370
+    row-label = #5c6370
+    separator = #5c6370
+    highlight = #3e4451
+    header = #61afef
+    ",
+  ),
+  (
+    "one-light",
+    "
+    gradient = #4078f2,#0184bc,#50a14f,#c18401,#986801,#e45649
+    gradient-space = lab
+    ascii = #4078f2,#50a14f,#986801,#a626a4,#a0a1a7
+    row-label = #a0a1a7
+    separator = #a0a1a7
+    highlight = #e5e5e6
+    header = #4078f2
+    ",
+  ),
+];
+
+impl Theme {
+  /// Parses a theme file. Anything it leaves out is taken from the default
+  /// theme.
+  pub fn parse(name: &str, src: &str) -> Result<Theme, String> {
+    let mut theme = Self::builtin("default").expect("no default theme");
+    theme.name = name.into();
+    theme.update(src)?;
+    Ok(theme)
+  }
+
+  /// Returns the built-in theme called `name`.
+  pub fn builtin(name: &str) -> Option<Theme> {
+    let &(name, src) = BUILTIN.iter().find(|&&(n, _)| n == name)?;
+    let mut theme = Theme {
+      name: name.into(),
+      gradient: String::new(),
+      gradient_space: String::new(),
+      ascii: String::new(),
+      row_label: None,
+      separator: None,
+      highlight: String::new(),
+      header: String::new(),
+    };
+    // The default theme is the base for every other one.
+    if name != "default" {
+      theme = Self::builtin("default")?;
+      theme.name = name.into();
+    }
+    theme.update(src).ok()?;
+    Some(theme)
+  }
+
+  /// Overwrites this theme with the keys set in `src`.
+  fn update(&mut self, src: &str) -> Result<(), String> {
+    for (i, line) in src.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| format!("line {}: expected `key = value`", i + 1))?;
+      let value = value.trim().to_string();
+      match key.trim() {
+        "gradient" => self.gradient = value,
+        "gradient-space" => self.gradient_space = value,
+        "ascii" => self.ascii = value,
+        "row-label" => self.row_label = Some(value),
+        "separator" => self.separator = Some(value),
+        "highlight" => self.highlight = value,
+        "header" => self.header = value,
+        key => return Err(format!("line {}: unknown key `{key}`", i + 1)),
+      }
+    }
+    Ok(())
+  }
+
+  /// Finds the theme called `name`: either a path to a theme file, a theme
+  /// in the user's theme directory, or a built-in theme.
+  pub fn find(name: &str) -> Result<Theme, String> {
+    let path = Path::new(name);
+    let is_file = path.extension().is_some_and(|ext| ext == "theme");
+    if is_file || path.components().count() > 1 {
+      return Self::load(path);
+    }
+    if let Some(dir) = user_dir() {
+      let path = dir.join(format!("{name}.theme"));
+      if path.is_file() {
+        return Self::load(&path);
+      }
+    }
+    Self::builtin(name).ok_or_else(|| {
+      format!("unknown theme `{name}`; see --list-themes for the options")
+    })
+  }
+
+  /// Loads a theme file, naming it after the file.
+  fn load(path: &Path) -> Result<Theme, String> {
+    let src = fs::read_to_string(path)
+      .map_err(|e| format!("{}: {e}", path.display()))?;
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    Self::parse(&name, &src).map_err(|e| format!("{}: {e}", path.display()))
+  }
+
+  /// Returns every built-in theme, followed by the user's themes.
+  pub fn all() -> Vec<Result<Theme, String>> {
+    let mut themes = BUILTIN
+      .iter()
+      .filter_map(|&(name, _)| Self::builtin(name))
+      .map(Ok)
+      .collect::<Vec<_>>();
+
+    let Some(entries) = user_dir().and_then(|dir| fs::read_dir(dir).ok())
+    else {
+      return themes;
+    };
+    let mut paths = entries
+      .filter_map(|e| Some(e.ok()?.path()))
+      .filter(|p| p.extension().is_some_and(|ext| ext == "theme"))
+      .collect::<Vec<_>>();
+    paths.sort();
+    themes.extend(paths.iter().map(|p| Self::load(p)));
+    themes
+  }
+}
+
+impl argh::FromArgValue for Theme {
+  fn from_arg_value(value: &str) -> Result<Self, String> {
+    Self::find(value)
+  }
+}
+
+/// Returns the directory that user themes are loaded from:
+/// `$XDG_CONFIG_HOME/0x/themes`, or `~/.config/0x/themes`.
+pub fn user_dir() -> Option<PathBuf> {
+  let config = env::var_os("XDG_CONFIG_HOME")
+    .filter(|v| !v.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| Some(Path::new(&env::var_os("HOME")?).join(".config")))?;
+  Some(config.join("0x").join("themes"))
+}
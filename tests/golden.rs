@@ -0,0 +1,327 @@
+// Copyright 2022 Miguel Young de la Sota
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Golden tests: dumps `tests/golden/input.bin` with each set of arguments
+//! below and compares the output, escapes and all, against
+//! `tests/golden/<name>.out`.
+//!
+//! Run with `OHX_BLESS=1` to write out the current outputs instead.
+
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+const CASES: &[(&str, &[&str])] = &[
+  ("plain", &["--color", "never"]),
+  ("truecolor", &["--color", "truecolor"]),
+  ("256", &["--color", "256"]),
+  ("16", &["--color", "16"]),
+  ("base2", &["-b", "2", "--color", "never"]),
+  ("base2-color", &["-b", "2", "--color", "truecolor"]),
+  ("base4", &["-b", "4", "--color", "truecolor"]),
+  ("base8", &["-b", "8", "--color", "never"]),
+  ("base8-color", &["-b", "8", "--color", "truecolor"]),
+  (
+    "base8-groups",
+    &["-b", "8", "-g", "6", "-c", "12", "--color", "never"],
+  ),
+  ("base32", &["-b", "32", "--color", "never"]),
+  ("base32-color", &["-b", "32", "--color", "256"]),
+  ("base64", &["-b", "64", "--color", "never"]),
+  ("base64-color", &["-b", "64", "--color", "truecolor"]),
+  (
+    "little-endian-base8",
+    &["-e", "-b", "8", "--color", "never"],
+  ),
+  (
+    "little-endian-base64",
+    &["-e", "-b", "64", "-g", "6", "--color", "truecolor"],
+  ),
+  ("little-endian", &["-e", "-g", "4", "--color", "never"]),
+  (
+    "little-endian-color",
+    &["-e", "-g", "4", "--color", "truecolor"],
+  ),
+  (
+    "little-endian-base2",
+    &["-e", "-b", "2", "-g", "2", "--color", "never"],
+  ),
+  ("groups", &["-g", "2", "--color", "never"]),
+  (
+    "groups-cols",
+    &["-g", "2", "-c", "8", "--color", "truecolor"],
+  ),
+  ("cols", &["-c", "24", "--color", "never"]),
+  ("seek", &["-s", "100", "--color", "never"]),
+  ("seek-back", &["-s", "-50", "--color", "truecolor"]),
+  ("limit", &["-l", "100", "--color", "never"]),
+  ("seek-limit", &["-s", "8", "-l", "8", "--color", "never"]),
+  ("offset", &["-o", "4096", "--color", "never"]),
+  (
+    "single-glyphs",
+    &["--color-single-glyphs", "--color", "truecolor"],
+  ),
+  ("formula", &["-x", "x3>>>1^", "--color", "truecolor"]),
+  (
+    "formula-base8",
+    &["-x", "x0x10*", "-b", "8", "--color", "truecolor"],
+  ),
+  ("no-ascii", &["-y", "none", "--color", "truecolor"]),
+  (
+    "words",
+    &["--row-label-style", "words", "-b", "8", "--color", "never"],
+  ),
+  ("lines", &["--row-label-style", "lines", "--color", "never"]),
+  (
+    "no-labels",
+    &["--row-label-style", "none", "--color", "never"],
+  ),
+  (
+    "annotate",
+    &["--annotate", "tests/golden/notes.txt", "--color", "never"],
+  ),
+  (
+    "annotate-color",
+    &[
+      "--annotate",
+      "tests/golden/notes.txt",
+      "--color",
+      "truecolor",
+    ],
+  ),
+  (
+    "footnotes",
+    &[
+      "--annotate",
+      "tests/golden/notes.txt",
+      "--footnotes",
+      "--range",
+      "0x50..0x120",
+      "--color",
+      "never",
+    ],
+  ),
+];
+
+fn golden_dir() -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Runs `0x` over `input` with `args`, returning its stdout. The input is
+/// piped in on stdin if `piped` is set.
+fn run(input: &Path, args: &[&str], piped: bool) -> Vec<u8> {
+  let mut cmd = Command::new(env!("CARGO_BIN_EXE_0x"));
+  cmd
+    .args(args)
+    .env_remove("NO_COLOR")
+    .env_remove("CLICOLOR_FORCE")
+    .env_remove("COLORTERM");
+  match piped {
+    true => cmd.stdin(File::open(input).unwrap()),
+    false => cmd.arg(input),
+  };
+
+  let output = cmd.output().unwrap();
+  assert!(
+    output.status.success(),
+    "0x {} failed: {}",
+    args.join(" "),
+    String::from_utf8_lossy(&output.stderr)
+  );
+  output.stdout
+}
+
+#[test]
+fn golden() {
+  let bless = std::env::var_os("OHX_BLESS").is_some();
+  let input = golden_dir().join("input.bin");
+  let mut failed = Vec::new();
+  for &(name, args) in CASES {
+    let path = golden_dir().join(format!("{name}.out"));
+    let got = run(&input, args, false);
+    if bless {
+      fs::write(&path, &got).unwrap();
+      continue;
+    }
+
+    let want = fs::read(&path).unwrap_or_default();
+    if got != want {
+      failed.push(name);
+    }
+  }
+
+  assert!(
+    failed.is_empty(),
+    "output changed for {failed:?}; rerun with OHX_BLESS=1 if that's intended"
+  );
+}
+
+/// Pipes are read and seeked around in differently from files, but should
+/// be drawn the same.
+#[test]
+fn piped() {
+  let input = golden_dir().join("input.bin");
+  for &(name, args) in CASES {
+    let path = golden_dir().join(format!("{name}.out"));
+    let want = fs::read(&path).unwrap_or_default();
+    assert!(
+      run(&input, args, true) == want,
+      "output changed for {name} on stdin"
+    );
+  }
+}
+
+/// Splitting the work between threads shouldn't change the output. This
+/// needs an input several times the size of the segments each thread takes
+/// on, so it's made up on the spot.
+#[test]
+fn jobs() {
+  let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("jobs.bin");
+  let golden = fs::read(golden_dir().join("input.bin")).unwrap();
+  fs::write(&input, golden.repeat(200)).unwrap();
+
+  let names = [
+    "truecolor",
+    "base2-color",
+    "base8-groups",
+    "base64-color",
+    "little-endian-color",
+    "seek-back",
+    "single-glyphs",
+    "lines",
+  ];
+  let named = CASES.iter().filter(|(name, _)| names.contains(name));
+  let extra: &[&[&str]] = &[
+    &["--range", "100..70000,-100000..", "--color", "truecolor"],
+    &[
+      "--checksum",
+      "crc32",
+      "--line-checksums",
+      "--color",
+      "never",
+    ],
+    &["--format", "jsonl", "-b", "8", "-l", "140000"],
+  ];
+  let cases = named.map(|&(_, args)| args).chain(extra.iter().copied());
+  for args in cases {
+    let one = run(&input, &[args, &["-j", "1"]].concat(), false);
+    let four = run(&input, &[args, &["-j", "4"]].concat(), false);
+    assert!(one == four, "-j 4 changed the output of {args:?}");
+  }
+}
+
+/// Compressed copies of the input, in each format this build can decode.
+fn compressed_inputs() -> Vec<PathBuf> {
+  let formats = [
+    ("gz", true),
+    ("zst", cfg!(feature = "zstd")),
+    ("xz", cfg!(feature = "xz")),
+    ("bz2", cfg!(feature = "bzip2")),
+  ];
+  formats
+    .into_iter()
+    .filter(|&(_, enabled)| enabled)
+    .map(|(ext, _)| golden_dir().join(format!("input.bin.{ext}")))
+    .collect()
+}
+
+/// Compressed inputs are dumped decompressed, and can be seeked around in,
+/// whether they're files or pipes.
+#[test]
+fn compressed() {
+  let names = ["plain", "seek", "seek-back", "seek-limit"];
+  for input in compressed_inputs() {
+    for &(name, args) in CASES.iter().filter(|(n, _)| names.contains(n)) {
+      let want = fs::read(golden_dir().join(format!("{name}.out"))).unwrap();
+      for piped in [false, true] {
+        assert!(
+          run(&input, args, piped) == want,
+          "output changed for {name} on {input:?} (piped: {piped})"
+        );
+      }
+    }
+
+    // --raw dumps the compressed bytes themselves.
+    let raw = run(&input, &["--raw", "--color", "never"], false);
+    let head = fs::read(&input).unwrap()[..2]
+      .iter()
+      .map(|b| format!("{b:02x}"))
+      .collect::<String>();
+    assert!(raw.starts_with(format!("0x00000000:  {head}").as_bytes()));
+  }
+}
+
+/// Inputs that start with a magic number, but don't decode, are dumped as
+/// they are.
+#[test]
+fn fake_magic() {
+  let golden = fs::read(golden_dir().join("input.bin")).unwrap();
+  for (i, magic) in [&b"\x1f\x8b\x08"[..], b"BZh91AY&SY"].iter().enumerate() {
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR"))
+      .join(format!("fake-magic-{i}.bin"));
+    fs::write(&input, [*magic, &golden[..]].concat()).unwrap();
+
+    let raw = run(&input, &["--raw", "--color", "never"], false);
+    for piped in [false, true] {
+      assert!(
+        run(&input, &["--color", "never"], piped) == raw,
+        "{magic:x?} wasn't dumped as it is (piped: {piped})"
+      );
+    }
+  }
+}
+
+/// With --compressed-offsets, rows are labeled with where they are in the
+/// compressed input, from just past the gzip header to its end.
+#[test]
+fn compressed_offsets() {
+  let input = golden_dir().join("input.bin.gz");
+  let len = fs::metadata(&input).unwrap().len();
+  let out = run(&input, &["--compressed-offsets", "--color", "never"], false);
+  let labels = String::from_utf8(out)
+    .unwrap()
+    .lines()
+    .map(|line| {
+      let label = line.split(':').next().unwrap();
+      u64::from_str_radix(label.trim_start_matches("0x"), 16).unwrap()
+    })
+    .collect::<Vec<_>>();
+
+  assert_eq!(labels.len(), 64);
+  assert!(labels[0] < 0x20, "first row labeled {:#x}", labels[0]);
+  assert!(labels.windows(2).all(|w| w[0] < w[1]), "{labels:x?}");
+  let last = *labels.last().unwrap();
+  assert!(
+    len - 0x40 < last && last < len,
+    "last row labeled {last:#x}"
+  );
+}
+
+/// Errors while reading stdin call it `<stdin>`, not `-`.
+#[test]
+fn stdin_errors() {
+  let gz = fs::read(golden_dir().join("input.bin.gz")).unwrap();
+  let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("truncated.bin.gz");
+  fs::write(&input, &gz[..gz.len() / 2]).unwrap();
+
+  let output = Command::new(env!("CARGO_BIN_EXE_0x"))
+    .stdin(File::open(&input).unwrap())
+    .output()
+    .unwrap();
+  assert!(!output.status.success());
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.starts_with("eks: <stdin>: "), "{stderr}");
+}